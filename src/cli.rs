@@ -57,6 +57,34 @@ pub struct Command {
     #[structopt(short = "k", long = "kmer", help = "path of kmer graph output file")]
     pub kmer: Option<String>,
 
+    #[structopt(long = "dot", help = "path of unitig graph output file in dot format")]
+    pub dot: Option<String>,
+
+    #[structopt(
+        long = "contigs",
+        help = "path where greedy contigs build from unitig graph are write in fasta format"
+    )]
+    pub contigs: Option<String>,
+
+    #[structopt(
+        long = "dominance-ratio",
+        default_value = "2",
+        help = "contig is extend to a successor if its coverage is higher than this ratio times the coverage of other successors"
+    )]
+    pub dominance_ratio: f64,
+
+    #[structopt(
+        long = "read-paths",
+        help = "path where unitig paths supported by reads are write in tsv format"
+    )]
+    pub read_paths: Option<String>,
+
+    #[structopt(
+        long = "gfa-paths",
+        help = "write unitig paths supported by reads in gfa output"
+    )]
+    pub gfa_paths: bool,
+
     #[structopt(
         short = "t",
         long = "edge-weight-threshold",
@@ -65,6 +93,80 @@ pub struct Command {
     )]
     pub edge_threshold: u8,
 
+    #[structopt(
+        long = "transitive-reduction",
+        help = "remove jump edge to a kmer reachable from another successor by a chain of shorter edges"
+    )]
+    pub transitive_reduction: bool,
+
+    #[structopt(
+        short = "T",
+        long = "threads",
+        default_value = "1",
+        help = "Number of thread use to count kmer and build unitigs"
+    )]
+    pub threads: usize,
+
+    #[structopt(
+        long = "gfa-version",
+        default_value = "1",
+        possible_values = &["1", "2"],
+        help = "version of gfa output, in gfa2 jump of more than one base are write as gap"
+    )]
+    pub gfa_version: u8,
+
+    #[structopt(
+        long = "tip-length",
+        help = "remove dead end unitig shorter than this length, see --tip-unit"
+    )]
+    pub tip_length: Option<usize>,
+
+    #[structopt(
+        long = "tip-unit",
+        default_value = "base",
+        possible_values = &["base", "kmer"],
+        help = "unit of --tip-length"
+    )]
+    pub tip_unit: String,
+
+    #[structopt(
+        long = "pop-bubbles",
+        help = "remove parallel unitigs between the same end kmers, the branch with higher coverage is keep"
+    )]
+    pub pop_bubbles: bool,
+
+    #[structopt(
+        long = "bubble-variants",
+        requires = "pop-bubbles",
+        help = "path where kept and popped bubble branch are write in fasta format"
+    )]
+    pub bubble_variants: Option<String>,
+
+    #[structopt(
+        long = "bubble-length-ratio",
+        default_value = "0.8",
+        help = "branch is popped only if its length is at least this fraction of the kept branch length, or the reverse"
+    )]
+    pub bubble_length_ratio: f64,
+
+    #[structopt(
+        long = "min-coverage",
+        help = "remove unitig with a mean kmer abundance lower than this value"
+    )]
+    pub min_coverage: Option<f64>,
+
+    #[structopt(
+        long = "relative-coverage",
+        help = "remove unitig with a mean kmer abundance lower than this fraction of its neighbors mean"
+    )]
+    pub relative_coverage: Option<f64>,
+
+    #[structopt(
+        long = "resolve-repeats",
+        help = "duplicate repeat unitigs when each in neighbour is connected to one out neighbour by at least this number of reads"
+    )]
+    pub resolve_repeats: Option<u64>,
+
     #[structopt(subcommand)]
     pub subcmd: SubCommand,
 
@@ -72,7 +174,7 @@ pub struct Command {
     pub unicorn: bool,
 }
 
-pub(crate) fn unicorn() -> Result<()> {
+pub fn unicorn() -> Result<()> {
     let mut stdout = StandardStream::stdout(ColorChoice::Always);
     stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
     writeln!(
@@ -273,6 +375,24 @@ pub(crate) fn unicorn() -> Result<()> {
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AbundanceMin {
+    Auto,
+    Value(u8),
+}
+
+impl std::str::FromStr for AbundanceMin {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "auto" {
+            Ok(AbundanceMin::Auto)
+        } else {
+            s.parse::<u8>().map(AbundanceMin::Value)
+        }
+    }
+}
+
 #[derive(StructOpt, Debug)]
 pub enum SubCommand {
     #[structopt(about = "Generate unitig graph from pcon count")]
@@ -286,10 +406,26 @@ pub struct Count {
     #[structopt(
         short = "i",
         long = "input",
-        required = true,
+        required_unless = "count",
+        conflicts_with = "count",
         help = "path to pcon solidity file"
     )]
-    pub input: String,
+    pub input: Option<String>,
+
+    #[structopt(
+        short = "c",
+        long = "count",
+        help = "path to pcon count file, abundance of kmer is keep"
+    )]
+    pub count: Option<String>,
+
+    #[structopt(
+        short = "a",
+        long = "abudance-min",
+        default_value = "1",
+        help = "with --count, keep only kmer with abudance is higher than this parametre, auto select it from kmer spectrum"
+    )]
+    pub abundance_min: AbundanceMin,
 }
 
 #[derive(StructOpt, Debug)]
@@ -298,15 +434,15 @@ pub struct Reads {
         short = "i",
         long = "input",
         required = true,
-        help = "path to reads file"
+        help = "path to reads file, fasta or fastq, compressed or not, - for stdin, can be repeated"
     )]
-    pub input: String,
+    pub input: Vec<String>,
 
     #[structopt(
         short = "k",
         long = "kmer-size",
         required = true,
-        help = "kmer size, if kmer size is even real value is equal to k-1, max value 31"
+        help = "kmer size, if kmer size is even real value is equal to k-1, max value 63"
     )]
     pub kmer_size: u8,
 
@@ -315,7 +451,38 @@ pub struct Reads {
         long = "abudance-min",
         default_value = "1",
         required = true,
-        help = "write only kmer with abudance is higher than this parametre"
+        help = "write only kmer with abudance is higher than this parametre, auto select it from kmer spectrum"
+    )]
+    pub abundance_min: AbundanceMin,
+
+    #[structopt(
+        short = "b",
+        long = "counter-bits",
+        default_value = "8",
+        possible_values = &["4", "8"],
+        help = "number of bits used to store kmer count, count saturate at 2^bits - 1"
+    )]
+    pub counter_bits: u8,
+
+    #[structopt(
+        short = "r",
+        long = "record-buffer",
+        default_value = "8192",
+        help = "number of reads counted in parallel"
+    )]
+    pub record_buffer: usize,
+
+    #[structopt(
+        short = "H",
+        long = "histogram",
+        help = "path where kmer abundance histogram is write in tsv format"
+    )]
+    pub histogram: Option<String>,
+
+    #[structopt(
+        short = "s",
+        long = "solidity",
+        help = "path where solidity bitfield is write in pcon format, max kmer size 17, can be reuse with count -i"
     )]
-    pub abundance_min: u8,
+    pub solidity: Option<String>,
 }
@@ -0,0 +1,357 @@
+/*
+Copyright (c) 2020 Pierre Marijon <pmarijon@mmci.uni-saarland.de>
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+
+/* crate use */
+use bv::BitsMut;
+
+/* dense counter use one cell for each canonical kmer, 4^k / 2 cells */
+pub const DENSE_MAX_K: u8 = 15;
+
+const NB_SHARD: usize = 256;
+
+thread_local! {
+    /* shard buckets are reuse by all sequences of a thread */
+    static BUCKETS: std::cell::RefCell<Vec<Vec<u128>>> = std::cell::RefCell::new(vec![Vec::new(); NB_SHARD]);
+}
+
+enum Storage {
+    Dense(Vec<std::sync::atomic::AtomicU8>),
+    Sparse(Vec<std::sync::Mutex<rustc_hash::FxHashMap<u128, u8>>>),
+}
+
+pub struct Counter {
+    k: u8,
+    bits: u8,
+    max: u8,
+    storage: Storage,
+}
+
+impl Counter {
+    pub fn new(k: u8, bits: u8) -> Self {
+        let storage = if k <= DENSE_MAX_K {
+            let nb_cell = crate::kmer::get_hash_space_size(k) as usize * bits as usize / 8;
+
+            Storage::Dense(
+                (0..nb_cell)
+                    .map(|_| std::sync::atomic::AtomicU8::new(0))
+                    .collect(),
+            )
+        } else {
+            Storage::Sparse(
+                (0..NB_SHARD)
+                    .map(|_| std::sync::Mutex::new(rustc_hash::FxHashMap::default()))
+                    .collect(),
+            )
+        };
+
+        Counter {
+            k,
+            bits,
+            max: ((1u16 << bits) - 1) as u8,
+            storage,
+        }
+    }
+
+    pub fn k(&self) -> u8 {
+        self.k
+    }
+
+    pub fn add_sequence(&self, seq: &[u8]) {
+        if seq.len() < self.k as usize {
+            return;
+        }
+
+        let mask = crate::kmer::mask(self.k);
+        let mut kmer = crate::kmer::seq2bit(&seq[..(self.k - 1) as usize]);
+
+        match &self.storage {
+            Storage::Dense(counts) => {
+                for nuc in &seq[(self.k - 1) as usize..] {
+                    kmer = ((kmer << 2) | crate::kmer::nuc2bit(*nuc)) & mask;
+
+                    self.increment(counts, crate::kmer::cannonical(kmer, self.k));
+                }
+            }
+            Storage::Sparse(shards) => BUCKETS.with(|buckets| {
+                /* group kmer by shard to lock each shard only once by sequence */
+                let mut buckets = buckets.borrow_mut();
+                for nuc in &seq[(self.k - 1) as usize..] {
+                    kmer = ((kmer << 2) | crate::kmer::nuc2bit(*nuc)) & mask;
+
+                    let cano = crate::kmer::cannonical(kmer, self.k);
+                    buckets[shard(cano)].push(cano);
+                }
+
+                for (bucket, shard) in buckets.iter_mut().zip(shards.iter()) {
+                    if bucket.is_empty() {
+                        continue;
+                    }
+
+                    let mut map = shard.lock().unwrap();
+                    for cano in bucket.drain(..) {
+                        let value = map.entry(cano).or_insert(0);
+                        if *value < self.max {
+                            *value += 1;
+                        }
+                    }
+                }
+            }),
+        }
+    }
+
+    fn increment(&self, counts: &[std::sync::atomic::AtomicU8], cano: u128) {
+        let hash = crate::kmer::remove_first_bit(cano) as usize;
+
+        if self.bits == 8 {
+            let _ = counts[hash].fetch_update(
+                std::sync::atomic::Ordering::Relaxed,
+                std::sync::atomic::Ordering::Relaxed,
+                |c| if c == self.max { None } else { Some(c + 1) },
+            );
+        } else {
+            let shift = (hash & 1) * 4;
+            let _ = counts[hash / 2].fetch_update(
+                std::sync::atomic::Ordering::Relaxed,
+                std::sync::atomic::Ordering::Relaxed,
+                |c| {
+                    if (c >> shift) & 0b1111 == self.max {
+                        None
+                    } else {
+                        Some(c + (1 << shift))
+                    }
+                },
+            );
+        }
+    }
+
+    pub fn set(&self, kmer: u128, value: u8) {
+        let cano = crate::kmer::cannonical(kmer, self.k);
+        let value = value.min(self.max);
+
+        match &self.storage {
+            Storage::Dense(counts) => {
+                let hash = crate::kmer::remove_first_bit(cano) as usize;
+
+                if self.bits == 8 {
+                    counts[hash].store(value, std::sync::atomic::Ordering::Relaxed);
+                } else {
+                    let shift = (hash & 1) * 4;
+                    let _ = counts[hash / 2].fetch_update(
+                        std::sync::atomic::Ordering::Relaxed,
+                        std::sync::atomic::Ordering::Relaxed,
+                        |c| Some((c & !(0b1111 << shift)) | (value << shift)),
+                    );
+                }
+            }
+            Storage::Sparse(shards) => {
+                shards[shard(cano)].lock().unwrap().insert(cano, value);
+            }
+        }
+    }
+
+    pub fn get(&self, kmer: u128) -> u8 {
+        let cano = crate::kmer::cannonical(kmer, self.k);
+
+        match &self.storage {
+            Storage::Dense(counts) => {
+                let hash = crate::kmer::remove_first_bit(cano) as usize;
+
+                if self.bits == 8 {
+                    counts[hash].load(std::sync::atomic::Ordering::Relaxed)
+                } else {
+                    (counts[hash / 2].load(std::sync::atomic::Ordering::Relaxed)
+                        >> ((hash & 1) * 4))
+                        & 0b1111
+                }
+            }
+            Storage::Sparse(shards) => {
+                *shards[shard(cano)].lock().unwrap().get(&cano).unwrap_or(&0)
+            }
+        }
+    }
+
+    pub fn histogram(&self) -> Vec<u64> {
+        match &self.storage {
+            Storage::Dense(_) => dense_histogram(self.k, self.max, |kmer| self.get(kmer)),
+            Storage::Sparse(shards) => {
+                let mut histogram = vec![0; self.max as usize + 1];
+                for shard in shards {
+                    for count in shard.lock().unwrap().values() {
+                        histogram[*count as usize] += 1;
+                    }
+                }
+
+                histogram
+            }
+        }
+    }
+
+    /* same rule as pcon, kmer is solid if its count is strictly higher than abundance_min */
+    pub fn solidity(&self, abundance_min: u8) -> crate::graph::kmer::Solidity {
+        match &self.storage {
+            Storage::Dense(_) => dense_solidity(self.k, abundance_min, |kmer| self.get(kmer)),
+            Storage::Sparse(shards) => {
+                let mut kmers = rustc_hash::FxHashSet::default();
+
+                for shard in shards {
+                    kmers.extend(
+                        shard
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .filter(|(_, count)| **count > abundance_min)
+                            .map(|(kmer, _)| *kmer),
+                    );
+                }
+
+                kmers.into()
+            }
+        }
+    }
+}
+
+/* loaded pcon count is already a dense table, it's use as is and can't be modified */
+pub enum Counts {
+    Counter(Counter),
+    Pcon(pcon::count::Count),
+}
+
+impl Counts {
+    pub fn k(&self) -> u8 {
+        match self {
+            Counts::Counter(counter) => counter.k(),
+            Counts::Pcon(count) => count.get_k(),
+        }
+    }
+
+    pub fn get(&self, kmer: u128) -> u8 {
+        match self {
+            Counts::Counter(counter) => counter.get(kmer),
+            Counts::Pcon(count) => {
+                count.get_count(crate::kmer::cannonical(kmer, count.get_k()) as u64)
+            }
+        }
+    }
+
+    pub fn histogram(&self) -> Vec<u64> {
+        match self {
+            Counts::Counter(counter) => counter.histogram(),
+            Counts::Pcon(_) => dense_histogram(self.k(), u8::MAX, |kmer| self.get(kmer)),
+        }
+    }
+
+    pub fn solidity(&self, abundance_min: u8) -> crate::graph::kmer::Solidity {
+        match self {
+            Counts::Counter(counter) => counter.solidity(abundance_min),
+            Counts::Pcon(_) => dense_solidity(self.k(), abundance_min, |kmer| self.get(kmer)),
+        }
+    }
+}
+
+impl From<Counter> for Counts {
+    fn from(counter: Counter) -> Self {
+        Counts::Counter(counter)
+    }
+}
+
+fn dense_histogram<F>(k: u8, max: u8, get: F) -> Vec<u64>
+where
+    F: Fn(u128) -> u8,
+{
+    let mut histogram = vec![0; max as usize + 1];
+
+    for hash in 0..cocktail::kmer::get_hash_space_size(k) {
+        histogram[get(crate::kmer::hash2kmer(hash as u128)) as usize] += 1;
+    }
+
+    histogram
+}
+
+fn dense_solidity<F>(k: u8, abundance_min: u8, get: F) -> crate::graph::kmer::Solidity
+where
+    F: Fn(u128) -> u8,
+{
+    let mut bitfield = bv::BitVec::new_fill(false, cocktail::kmer::get_hash_space_size(k));
+
+    for hash in 0..cocktail::kmer::get_hash_space_size(k) {
+        if get(crate::kmer::hash2kmer(hash as u128)) > abundance_min {
+            bitfield.set_bit(hash, true);
+        }
+    }
+
+    bitfield.into()
+}
+
+pub fn first_valley(histogram: &[u64]) -> Option<u8> {
+    /* bin 0 contains all absent kmer, error peak begin at 1 */
+    for i in 1..histogram.len().saturating_sub(1) {
+        if histogram[i] < histogram[i + 1] {
+            return Some(i as u8);
+        }
+    }
+
+    None
+}
+
+fn shard(cano: u128) -> usize {
+    (crate::kmer::remove_first_bit(cano) as usize) % NB_SHARD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturate() {
+        let seq = b"ACTGACTGACTGACTGACTGACTGACTGACTGACTGACTGACTGACTGACTGACTGACTGACTGACTGACTG";
+        let actg = crate::kmer::seq2bit(b"ACTGA");
+
+        for (k, bits, expected) in &[(5, 8, 17), (5, 4, 15), (33, 4, 10)] {
+            let counter = Counter::new(*k, *bits);
+            counter.add_sequence(seq);
+
+            let kmer = crate::kmer::seq2bit(&seq[..*k as usize]);
+            assert_eq!(counter.get(kmer), *expected);
+            assert_eq!(counter.get(crate::kmer::revcomp(kmer, *k)), *expected);
+        }
+
+        let counter = Counter::new(5, 4);
+        counter.add_sequence(b"ACTGA");
+        assert_eq!(counter.get(actg), 1);
+        assert_eq!(counter.get(crate::kmer::seq2bit(b"CTGAC")), 0);
+    }
+
+    #[test]
+    fn histogram_valley() {
+        let counter = Counter::new(5, 8);
+        counter.add_sequence(b"ACTGACTGA");
+
+        let histogram = counter.histogram();
+        assert_eq!(histogram.len(), 256);
+        assert_eq!(histogram[1], 3);
+        assert_eq!(histogram[2], 1);
+
+        assert_eq!(first_valley(&[0, 100, 40, 10, 20, 50, 30]), Some(3));
+        assert_eq!(first_valley(&[0, 100, 40, 10, 5]), None);
+        assert_eq!(first_valley(&[0, 10, 20, 50]), Some(1));
+    }
+}
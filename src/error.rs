@@ -41,6 +41,15 @@ pub enum Error {
     #[error("Error durring writing of file {filename:}")]
     WritingError { filename: String },
 
+    #[error("Format of file '{filename:}' isn't recognized, first character isn't '>' (fasta) or '@' (fastq)")]
+    UnknownFormat { filename: String },
+
+    #[error("Kmer size {k:} is larger than the maximum supported value {max:}")]
+    KmerSizeTooLarge { k: u8, max: u8 },
+
+    #[error("Kmer size {k:} is smaller than the minimum supported value {min:}")]
+    KmerSizeTooSmall { k: u8, min: u8 },
+
     #[allow(dead_code)]
     #[error("If you get this error please contact the author with this message and command line you use: {name:?}")]
     NotReachableCode { name: String },
@@ -0,0 +1,173 @@
+/*
+Copyright (c) 2020 Pierre Marijon <pmarijon@mmci.uni-saarland.de>
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+
+/* project use */
+use crate::graph;
+use crate::graph::path::Path;
+use crate::graph::simplify::coverage;
+use crate::graph::unitig::{Link, Side, UnitigGraph};
+
+/* crate use */
+use anyhow::Result;
+
+pub struct Contig {
+    pub path: Path,
+    pub seq: Vec<u8>,
+}
+
+/* successor with a coverage higher than ratio times the coverage of others successors */
+fn dominant(unitig_graph: &UnitigGraph, side: Side<usize>, ratio: f64) -> Option<Link<usize>> {
+    let mut succs: Vec<(f64, Link<usize>)> = unitig_graph
+        .graph
+        .successors(side)
+        .map(|link| (coverage(&unitig_graph.unitigs[link.to.id]), *link))
+        .collect();
+
+    succs.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    match succs.as_slice() {
+        [] => None,
+        [(_, link)] => Some(*link),
+        [(best, link), (second, _), ..] if *best > *second * ratio => Some(*link),
+        _ => None,
+    }
+}
+
+fn extend(
+    unitig_graph: &UnitigGraph,
+    start: Side<usize>,
+    visited: &mut [bool],
+    ratio: f64,
+) -> Vec<Link<usize>> {
+    let mut links = Vec::new();
+    let mut side = start;
+
+    while let Some(link) = dominant(unitig_graph, side, ratio) {
+        if visited[link.to.id] {
+            break;
+        }
+
+        visited[link.to.id] = true;
+        links.push(link);
+        side = link.to;
+    }
+
+    links
+}
+
+fn side_seq(unitig_graph: &UnitigGraph, side: Side<usize>) -> Vec<u8> {
+    let seq = &unitig_graph.unitigs[side.id].seq;
+
+    if side.forward {
+        seq.clone()
+    } else {
+        bio::alphabets::dna::revcomp(seq)
+    }
+}
+
+/* unitigs are used as seed by decreasing coverage, each unitig is in one contig */
+pub fn build_contigs(unitig_graph: &UnitigGraph, ratio: f64) -> Vec<Contig> {
+    let mut seeds: Vec<usize> = (0..unitig_graph.unitigs.len()).collect();
+    seeds.sort_by(|a, b| {
+        coverage(&unitig_graph.unitigs[*b])
+            .partial_cmp(&coverage(&unitig_graph.unitigs[*a]))
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(
+                unitig_graph.unitigs[*b]
+                    .seq
+                    .len()
+                    .cmp(&unitig_graph.unitigs[*a].seq.len()),
+            )
+            .then(a.cmp(b))
+    });
+
+    let mut visited = vec![false; unitig_graph.unitigs.len()];
+    let mut contigs = Vec::new();
+
+    for seed in seeds {
+        if visited[seed] {
+            continue;
+        }
+        visited[seed] = true;
+
+        let forward = extend(unitig_graph, Side::new(seed, true), &mut visited, ratio);
+        let backward = extend(unitig_graph, Side::new(seed, false), &mut visited, ratio);
+
+        let mut links: Vec<Link<usize>> = backward.iter().rev().map(|l| l.reverse()).collect();
+        links.extend(forward);
+
+        let first = links
+            .first()
+            .map_or(Side::new(seed, true), |link| link.from);
+        let mut path = vec![first];
+        let mut seq = side_seq(unitig_graph, first);
+        for link in links {
+            seq.extend_from_slice(&side_seq(unitig_graph, link.to)[link.ovl_len as usize..]);
+            path.push(link.to);
+        }
+
+        contigs.push(Contig { path, seq });
+    }
+
+    contigs
+}
+
+pub fn write_contigs<W>(writer: &mut W, contigs: &[Contig]) -> Result<()>
+where
+    W: std::io::Write,
+{
+    for (id, contig) in contigs.iter().enumerate() {
+        writeln!(
+            writer,
+            ">{} LN:i:{} path:Z:{}\n{}",
+            id,
+            contig.seq.len(),
+            graph::path::path2string(&contig.path, ","),
+            std::str::from_utf8(&contig.seq)?,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn greedy() {
+        let k = 7;
+        let major = graph::fixtures::MAJOR;
+
+        let solid = graph::fixtures::variant_graph(k, 3, 1);
+        let unitig_graph = UnitigGraph::new(k, &solid);
+        assert_eq!(unitig_graph.unitigs.len(), 4);
+
+        let contigs = build_contigs(&unitig_graph, 1.5);
+        assert_eq!(contigs.len(), 2);
+        assert_eq!(contigs[0].path.len(), 3);
+        assert!(contigs[0].seq == major || contigs[0].seq == bio::alphabets::dna::revcomp(major));
+
+        let contigs = build_contigs(&unitig_graph, 4.0);
+        assert_eq!(contigs.len(), 4);
+    }
+}
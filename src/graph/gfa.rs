@@ -0,0 +1,171 @@
+/*
+Copyright (c) 2020 Pierre Marijon <pmarijon@mmci.uni-saarland.de>
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+
+/* crate use */
+use anyhow::Result;
+
+pub fn write_header<W>(writer: &mut W, version: u8) -> Result<()>
+where
+    W: std::io::Write,
+{
+    writeln!(writer, "H\tVN:Z:{}.0", version)?;
+
+    Ok(())
+}
+
+pub fn write_segment<W, T>(writer: &mut W, version: u8, id: T, seq: &[u8]) -> Result<()>
+where
+    W: std::io::Write,
+    T: std::fmt::Display,
+{
+    if version == 2 {
+        write!(writer, "S\t{}\t{}\t", id, seq.len())?;
+    } else {
+        write!(writer, "S\t{}\t", id)?;
+    }
+    writer.write_all(seq)?;
+
+    Ok(())
+}
+
+pub fn write_jump_tags<W>(
+    writer: &mut W,
+    separator: char,
+    nb_jump: usize,
+    skipped: usize,
+    nb_inferred: usize,
+) -> Result<()>
+where
+    W: std::io::Write,
+{
+    write!(
+        writer,
+        "{sep}jp:i:{}{sep}js:i:{}{sep}ji:i:{}",
+        nb_jump,
+        skipped,
+        nb_inferred,
+        sep = separator
+    )?;
+
+    Ok(())
+}
+
+/* segment are (id, orientation, length), a jump of more than one base is write as a gap in gfa2 */
+pub fn write_link<W, T>(
+    writer: &mut W,
+    version: u8,
+    first: (T, char, usize),
+    second: (T, char, usize),
+    ovl_len: u8,
+    k: u8,
+    supported: bool,
+) -> Result<()>
+where
+    W: std::io::Write,
+    T: std::fmt::Display,
+{
+    let deep = k - ovl_len;
+
+    if version != 2 {
+        write!(
+            writer,
+            "L\t{}\t{}\t{}\t{}\t{}M",
+            first.0, first.1, second.0, second.1, ovl_len
+        )?;
+    } else if deep > 1 {
+        write!(
+            writer,
+            "G\t*\t{}{}\t{}{}\t-{}\t*",
+            first.0, first.1, second.0, second.1, ovl_len
+        )?;
+    } else {
+        let ovl_len = ovl_len as usize;
+        let (beg1, end1) = if first.1 == '+' {
+            (first.2 - ovl_len, first.2)
+        } else {
+            (0, ovl_len)
+        };
+        let (beg2, end2) = if second.1 == '+' {
+            (0, ovl_len)
+        } else {
+            (second.2 - ovl_len, second.2)
+        };
+
+        write!(
+            writer,
+            "E\t*\t{}{}\t{}{}\t{}\t{}\t{}\t{}\t{}M",
+            first.0,
+            first.1,
+            second.0,
+            second.1,
+            position(beg1, first.2),
+            position(end1, first.2),
+            position(beg2, second.2),
+            position(end2, second.2),
+            ovl_len
+        )?;
+    }
+
+    if deep > 1 {
+        write_jump_tags(
+            writer,
+            '\t',
+            1,
+            deep as usize - 1,
+            if supported { 0 } else { 1 },
+        )?;
+    }
+    writer.write_all(b"\n")?;
+
+    Ok(())
+}
+
+fn position(pos: usize, len: usize) -> String {
+    if pos == len {
+        format!("{}$", pos)
+    } else {
+        pos.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gfa2_link() {
+        let mut out = Vec::new();
+
+        write_link(&mut out, 2, (0, '+', 10), (1, '-', 8), 5, 5, true).unwrap();
+        write_link(&mut out, 2, (0, '-', 10), (1, '+', 8), 4, 5, true).unwrap();
+        write_link(&mut out, 2, (0, '+', 10), (1, '+', 8), 2, 5, true).unwrap();
+        write_link(&mut out, 1, (0, '+', 10), (1, '+', 8), 2, 5, false).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "E\t*\t0+\t1-\t5\t10$\t3\t8$\t5M\n\
+             E\t*\t0-\t1+\t0\t4\t0\t4\t4M\n\
+             G\t*\t0+\t1+\t-2\t*\tjp:i:1\tjs:i:2\tji:i:0\n\
+             L\t0\t+\t1\t+\t2M\tjp:i:1\tjs:i:2\tji:i:1\n"
+        );
+    }
+}
@@ -22,13 +22,15 @@ SOFTWARE.
 
 /* crate use */
 use anyhow::Result;
+use bv::Bits;
 use itertools::Itertools;
+use rayon::prelude::*;
 use rustc_hash;
 
-fn build_kmermasks(deep: u8, k: u8) -> Vec<u64> {
+fn build_kmermasks(deep: u8, k: u8) -> Vec<u128> {
     let mut kmermasks = Vec::new();
 
-    let mut mask = (1 << (k as u64 * 2)) - 1;
+    let mut mask = crate::kmer::mask(k);
 
     for _ in 0..(deep) {
         mask >>= 2;
@@ -38,7 +40,7 @@ fn build_kmermasks(deep: u8, k: u8) -> Vec<u64> {
     kmermasks
 }
 
-fn build_subkmer(deep: u8) -> Vec<Vec<u64>> {
+fn build_subkmer(deep: u8) -> Vec<Vec<u128>> {
     let mut kseq = Vec::new();
 
     let nucs = "ACTG";
@@ -46,47 +48,359 @@ fn build_subkmer(deep: u8) -> Vec<Vec<u64>> {
         let length = i + 1;
         kseq.push(Vec::new());
         for multi_nucs in (0..length).map(|_| nucs.bytes()).multi_cartesian_product() {
-            kseq[i as usize].push(cocktail::kmer::seq2bit(multi_nucs.into_iter().as_slice()));
+            kseq[i as usize].push(crate::kmer::seq2bit(multi_nucs.into_iter().as_slice()));
         }
     }
 
     kseq
 }
 
+pub enum Solidity {
+    Bitfield(bv::BitVec<u8>),
+    Kmers(rustc_hash::FxHashSet<u128>),
+}
+
+impl From<bv::BitVec<u8>> for Solidity {
+    fn from(bitfield: bv::BitVec<u8>) -> Self {
+        Solidity::Bitfield(bitfield)
+    }
+}
+
+impl From<rustc_hash::FxHashSet<u128>> for Solidity {
+    fn from(kmers: rustc_hash::FxHashSet<u128>) -> Self {
+        Solidity::Kmers(kmers)
+    }
+}
+
+/* a sorted kmer cost 128 bits, a bitfield cost 1 bit for each possible kmer */
+const SORTED_KMER_COST: u128 = 128;
+
+fn bitfield_kmers(bitfield: &bv::BitVec<u8>) -> impl Iterator<Item = u128> + '_ {
+    (0..bitfield.block_len())
+        .filter(move |i| bitfield.get_block(*i) != 0)
+        .flat_map(move |i| {
+            let block = bitfield.get_block(i);
+
+            (0..8)
+                .filter(move |j| block & (1 << j) != 0)
+                .map(move |j| crate::kmer::hash2kmer((i * 8 + j) as u128))
+        })
+}
+
+enum Backend {
+    Bitfield(bv::BitVec<u8>),
+    Sorted(Vec<u128>),
+}
+
+impl Backend {
+    fn new(solidity: Solidity, k: u8) -> Self {
+        match solidity {
+            Solidity::Bitfield(bitfield) => {
+                let nb_solid = (0..bitfield.block_len())
+                    .map(|i| bitfield.get_block(i).count_ones() as u128)
+                    .sum();
+
+                if Backend::use_bitfield(nb_solid, k) {
+                    Backend::Bitfield(bitfield)
+                } else {
+                    let mut kmers = Vec::with_capacity(nb_solid as usize);
+                    kmers.extend(bitfield_kmers(&bitfield));
+
+                    kmers.sort_unstable();
+                    Backend::Sorted(kmers)
+                }
+            }
+            Solidity::Kmers(set) => {
+                if Backend::use_bitfield(set.len() as u128, k) {
+                    let mut bitfield =
+                        bv::BitVec::new_fill(false, cocktail::kmer::get_hash_space_size(k));
+
+                    for kmer in set {
+                        bitfield.set(crate::kmer::remove_first_bit(kmer) as u64, true);
+                    }
+
+                    Backend::Bitfield(bitfield)
+                } else {
+                    let mut kmers: Vec<u128> = set.into_iter().collect();
+
+                    kmers.sort_unstable();
+                    Backend::Sorted(kmers)
+                }
+            }
+        }
+    }
+
+    fn use_bitfield(nb_solid: u128, k: u8) -> bool {
+        k <= 31 && nb_solid * SORTED_KMER_COST >= crate::kmer::get_hash_space_size(k)
+    }
+
+    fn contains(&self, cano: u128) -> bool {
+        match self {
+            Backend::Bitfield(bitfield) => bitfield.get(crate::kmer::remove_first_bit(cano) as u64),
+            Backend::Sorted(kmers) => kmers.binary_search(&cano).is_ok(),
+        }
+    }
+
+    fn remove(&mut self, canos: &rustc_hash::FxHashSet<u128>) {
+        match self {
+            Backend::Bitfield(bitfield) => {
+                for cano in canos {
+                    bitfield.set(crate::kmer::remove_first_bit(*cano) as u64, false);
+                }
+            }
+            Backend::Sorted(kmers) => kmers.retain(|cano| !canos.contains(cano)),
+        }
+    }
+}
+
 pub struct Graph {
-    solidity: rustc_hash::FxHashSet<u64>,
-    kmermasks: Vec<u64>,
-    subkmer: Vec<Vec<u64>>,
+    solidity: Backend,
+    abundance: Option<crate::counter::Counts>,
+    kmermasks: Vec<u128>,
+    subkmer: Vec<Vec<u128>>,
     max_deep: u8,
+    reduced: rustc_hash::FxHashSet<(u128, u128)>,
     k: u8,
 }
 
 impl Graph {
-    pub fn new(solidity: bv::BitVec<u8>, k: u8, max_deep: u8) -> Self {
-        let mut set = rustc_hash::FxHashSet::default();
+    pub fn new(solidity: Solidity, k: u8, max_deep: u8) -> Self {
+        let solidity = Backend::new(solidity, k);
 
-        for hash in 0..cocktail::kmer::get_hash_space_size(k) {
-            if solidity.get(hash) {
-                set.insert(hash);
+        match &solidity {
+            Backend::Bitfield(_) => info!("Solidity stored in bitfield"),
+            Backend::Sorted(kmers) => {
+                info!("Solidity stored in sorted array of {} kmer", kmers.len())
             }
         }
 
         Graph {
-            solidity: set,
+            solidity,
+            abundance: None,
             kmermasks: build_kmermasks(max_deep, k),
             subkmer: build_subkmer(max_deep),
             max_deep,
+            reduced: rustc_hash::FxHashSet::default(),
             k,
         }
     }
 
-    pub fn is_solid(&self, kmer: u64) -> bool {
-        self.solidity.contains(&cocktail::kmer::remove_first_bit(
-            cocktail::kmer::cannonical(kmer, self.k),
+    pub fn k(&self) -> u8 {
+        self.k
+    }
+
+    pub fn set_abundance<C>(&mut self, counts: C)
+    where
+        C: Into<crate::counter::Counts>,
+    {
+        self.abundance = Some(counts.into());
+    }
+
+    pub fn has_abundance(&self) -> bool {
+        self.abundance.is_some()
+    }
+
+    pub fn abundance(&self, kmer: u128) -> Option<u8> {
+        self.abundance.as_ref().map(|counts| counts.get(kmer))
+    }
+
+    /* a jump is supported if all skipped kmer are present in reads, even if they aren't solid */
+    pub fn jump_support(&self, kmer: u128, next: u128, deep: u8) -> bool {
+        if deep < 2 {
+            return true;
+        }
+
+        if !self.has_abundance() {
+            return false;
+        }
+
+        let mut seq = crate::kmer::kmer2seq(kmer, self.k).into_bytes();
+        seq.extend_from_slice(
+            &crate::kmer::kmer2seq(next, self.k).as_bytes()[(self.k - deep) as usize..],
+        );
+
+        seq.windows(self.k as usize)
+            .skip(1)
+            .take(deep as usize - 1)
+            .all(|subseq| self.abundance(crate::kmer::seq2bit(subseq)).unwrap_or(0) > 0)
+    }
+
+    pub fn remove_kmers<I>(&mut self, kmers: I)
+    where
+        I: IntoIterator<Item = u128>,
+    {
+        let canos: rustc_hash::FxHashSet<u128> = kmers
+            .into_iter()
+            .map(|kmer| crate::kmer::cannonical(kmer, self.k))
+            .collect();
+
+        self.solidity.remove(&canos);
+    }
+
+    pub fn is_solid(&self, kmer: u128) -> bool {
+        self.solidity
+            .contains(crate::kmer::cannonical(kmer, self.k))
+    }
+
+    pub fn solid_kmers(&self) -> Box<dyn Iterator<Item = u128> + '_> {
+        match &self.solidity {
+            Backend::Bitfield(bitfield) => Box::new(bitfield_kmers(bitfield)),
+            Backend::Sorted(kmers) => Box::new(kmers.iter().copied()),
+        }
+    }
+
+    pub fn par_solid_kmers(&self) -> impl rayon::iter::ParallelIterator<Item = u128> + '_ {
+        match &self.solidity {
+            Backend::Bitfield(bitfield) => rayon::iter::Either::Left(
+                (0..bitfield.block_len())
+                    .into_par_iter()
+                    .filter(move |i| bitfield.get_block(*i) != 0)
+                    .flat_map_iter(move |i| {
+                        let block = bitfield.get_block(i);
+
+                        (0..8)
+                            .filter(move |j| block & (1 << j) != 0)
+                            .map(move |j| crate::kmer::hash2kmer((i * 8 + j) as u128))
+                    }),
+            ),
+            Backend::Sorted(kmers) => rayon::iter::Either::Right(kmers.par_iter().copied()),
+        }
+    }
+
+    fn index_space(&self) -> u64 {
+        match &self.solidity {
+            Backend::Bitfield(bitfield) => bitfield.len(),
+            Backend::Sorted(kmers) => kmers.len() as u64,
+        }
+    }
+
+    fn index(&self, kmer: u128) -> Option<u64> {
+        let cano = crate::kmer::cannonical(kmer, self.k);
+
+        match &self.solidity {
+            Backend::Bitfield(bitfield) => {
+                let hash = crate::kmer::remove_first_bit(cano) as u64;
+                if bitfield.get(hash) {
+                    Some(hash)
+                } else {
+                    None
+                }
+            }
+            Backend::Sorted(kmers) => kmers.binary_search(&cano).ok().map(|x| x as u64),
+        }
+    }
+
+    /* an edge and its reverse complement are store as the same pair */
+    fn edge(&self, kmer: u128, next: u128) -> (u128, u128) {
+        (kmer, next).min((
+            crate::kmer::revcomp(next, self.k),
+            crate::kmer::revcomp(kmer, self.k),
         ))
     }
 
-    pub fn successors(&self, kmer: u64) -> Option<(Vec<u64>, u8)> {
+    fn reach_by_shorter(&self, starts: &[u128], target: u128, deep: u8) -> bool {
+        let mut current: Vec<u128> = starts.iter().copied().filter(|x| *x != target).collect();
+
+        for _ in 0..self.max_deep {
+            let mut next = Vec::new();
+
+            for kmer in current {
+                if let Some((succs, d)) = self.raw_successors(kmer) {
+                    if d >= deep {
+                        continue;
+                    }
+
+                    if succs.contains(&target) {
+                        return true;
+                    }
+
+                    next.extend(succs);
+                }
+            }
+
+            current = next;
+        }
+
+        false
+    }
+
+    /* a jump is implied if its end is reach from another successor by a chain of shorter edges */
+    fn implied_jumps(&self, kmer: u128) -> Vec<(u128, u128)> {
+        let (succs, deep) = match self.raw_successors(kmer) {
+            Some((succs, deep)) if deep > 1 && succs.len() > 1 => (succs, deep),
+            _ => return Vec::new(),
+        };
+
+        let implied: Vec<u128> = succs
+            .iter()
+            .copied()
+            .filter(|succ| self.reach_by_shorter(&succs, *succ, deep))
+            .collect();
+
+        /* successors reachable from each other are all keep */
+        if implied.len() == succs.len() {
+            return Vec::new();
+        }
+
+        implied
+            .into_iter()
+            .map(|succ| self.edge(kmer, succ))
+            .collect()
+    }
+
+    pub fn transitive_reduction(&mut self) -> usize {
+        let reduced: rustc_hash::FxHashSet<(u128, u128)> = self
+            .par_solid_kmers()
+            .flat_map_iter(|cano| {
+                let mut edges = self.implied_jumps(cano);
+                edges.extend(self.implied_jumps(crate::kmer::revcomp(cano, self.k)));
+
+                edges
+            })
+            .collect();
+
+        self.reduced = reduced;
+
+        self.reduced.len()
+    }
+
+    pub fn successors(&self, kmer: u128) -> Option<(Vec<u128>, u8)> {
+        let (succs, deep) = self.raw_successors(kmer)?;
+        if self.reduced.is_empty() || deep < 2 {
+            return Some((succs, deep));
+        }
+
+        let succs: Vec<u128> = succs
+            .into_iter()
+            .filter(|succ| !self.reduced.contains(&self.edge(kmer, *succ)))
+            .collect();
+
+        if succs.is_empty() {
+            None
+        } else {
+            Some((succs, deep))
+        }
+    }
+
+    pub fn predecessors(&self, kmer: u128) -> Option<(Vec<u128>, u8)> {
+        let (preds, deep) = self.raw_predecessors(kmer)?;
+        if self.reduced.is_empty() || deep < 2 {
+            return Some((preds, deep));
+        }
+
+        let preds: Vec<u128> = preds
+            .into_iter()
+            .filter(|pred| !self.reduced.contains(&self.edge(*pred, kmer)))
+            .collect();
+
+        if preds.is_empty() {
+            None
+        } else {
+            Some((preds, deep))
+        }
+    }
+
+    fn raw_successors(&self, kmer: u128) -> Option<(Vec<u128>, u8)> {
         for deep in 0..self.max_deep {
             let prefix = (kmer & self.kmermasks[deep as usize]) << (2 * (deep + 1));
 
@@ -94,7 +408,7 @@ impl Graph {
 
             for suffix in self.subkmer[deep as usize].iter() {
                 let next_kmer = prefix ^ suffix;
-                if next_kmer == kmer || cocktail::kmer::revcomp(next_kmer, self.k) == kmer {
+                if next_kmer == kmer || crate::kmer::revcomp(next_kmer, self.k) == kmer {
                     continue;
                 }
 
@@ -111,7 +425,7 @@ impl Graph {
         None
     }
 
-    pub fn predecessors(&self, kmer: u64) -> Option<(Vec<u64>, u8)> {
+    fn raw_predecessors(&self, kmer: u128) -> Option<(Vec<u128>, u8)> {
         for deep in 0..self.max_deep {
             let suffix = kmer >> (2 * (deep + 1));
 
@@ -122,7 +436,7 @@ impl Graph {
                 .map(|x| x << (2 * (self.k - (deep + 1))))
             {
                 let next_kmer = prefix ^ suffix;
-                if next_kmer == kmer || cocktail::kmer::revcomp(next_kmer, self.k) == kmer {
+                if next_kmer == kmer || crate::kmer::revcomp(next_kmer, self.k) == kmer {
                     continue;
                 }
 
@@ -140,92 +454,184 @@ impl Graph {
     }
 }
 
-pub struct Viewed {
-    bitvec: bv::BitVec<u8>,
-    k: u8,
+pub struct Viewed<'a> {
+    solid: &'a Graph,
+    bits: Vec<std::sync::atomic::AtomicU64>,
 }
 
-impl Viewed {
-    pub fn new(len: u64, k: u8) -> Self {
+impl<'a> Viewed<'a> {
+    pub fn new(solid: &'a Graph) -> Self {
+        let len = solid.index_space() / 64 + 1;
+
         Viewed {
-            bitvec: bv::BitVec::new_fill(false, len),
-            k,
+            solid,
+            bits: (0..len)
+                .map(|_| std::sync::atomic::AtomicU64::new(0))
+                .collect(),
         }
     }
 
-    pub fn contains(&self, kmer: u64) -> bool {
-        self.bitvec.get(cocktail::kmer::remove_first_bit(
-            cocktail::kmer::cannonical(kmer, self.k),
-        ))
+    pub fn contains(&self, kmer: u128) -> bool {
+        if let Some(index) = self.solid.index(kmer) {
+            self.bits[(index / 64) as usize].load(std::sync::atomic::Ordering::Relaxed)
+                & (1 << (index % 64))
+                != 0
+        } else {
+            false
+        }
     }
 
-    pub fn insert(&mut self, kmer: u64) {
-        self.bitvec.set(
-            cocktail::kmer::remove_first_bit(cocktail::kmer::cannonical(kmer, self.k)),
-            true,
-        );
+    /* return true if kmer wasn't already viewed */
+    pub fn insert(&self, kmer: u128) -> bool {
+        if let Some(index) = self.solid.index(kmer) {
+            let mask = 1 << (index % 64);
+
+            self.bits[(index / 64) as usize].fetch_or(mask, std::sync::atomic::Ordering::Relaxed)
+                & mask
+                == 0
+        } else {
+            false
+        }
     }
 }
 
-pub fn write_kmer_graph<W>(writer: &mut W, k: u8, solid: &Graph) -> Result<()>
-where
-    W: std::io::Write,
-{
-    writeln!(writer, "H\tVN:Z:1.0")?;
-    for kmer in 0..cocktail::kmer::get_kmer_space_size(k) {
-        if !solid.is_solid(kmer) {
-            continue;
+/* links start from the two sides of a canonical kmer */
+fn kmer_links(k: u8, solid: &Graph, cano: u128) -> Vec<crate::graph::unitig::Link<u128>> {
+    let mut links = Vec::new();
+
+    for forward in &[true, false] {
+        let kmer = if *forward {
+            cano
+        } else {
+            crate::kmer::revcomp(cano, k)
+        };
+
+        if let Some((succs, deep)) = solid.successors(kmer) {
+            for succ in succs {
+                let succ_cano = crate::kmer::cannonical(succ, k);
+
+                links.push(crate::graph::unitig::Link::new(
+                    crate::graph::unitig::Side::new(cano, *forward),
+                    crate::graph::unitig::Side::new(succ_cano, succ == succ_cano),
+                    k - deep,
+                    solid.jump_support(kmer, succ, deep),
+                ));
+            }
         }
+    }
 
-        let cano = cocktail::kmer::cannonical(kmer, k);
+    links
+}
 
-        writeln!(
+/* records are write while solid kmer are iterate, a link is write by the kmer that produce its
+canonical form, or by the other end if this kmer doesn't produce it */
+pub fn write_kmer_graph<W>(writer: &mut W, k: u8, solid: &Graph, version: u8) -> Result<()>
+where
+    W: std::io::Write,
+{
+    crate::graph::gfa::write_header(writer, version)?;
+    for cano in solid.solid_kmers() {
+        crate::graph::gfa::write_segment(
             writer,
-            "S\t{}\t{}\tRC:Z:{} RB:i:{}",
+            version,
             cano,
-            cocktail::kmer::kmer2seq(cano, k),
-            cocktail::kmer::kmer2seq(cocktail::kmer::revcomp(cano, k), k),
-            cocktail::kmer::revcomp(cano, k)
+            crate::kmer::kmer2seq(cano, k).as_bytes(),
+        )?;
+        writeln!(
+            writer,
+            "\tRC:Z:{} RB:i:{}",
+            crate::kmer::kmer2seq(crate::kmer::revcomp(cano, k), k),
+            crate::kmer::revcomp(cano, k)
         )?;
+    }
 
-        if let Some((preds, ovl_len)) = solid.predecessors(cano) {
-            for predecessor in preds {
-                let pred_sign = if cocktail::kmer::parity_even(predecessor) {
-                    ('+', '-')
-                } else {
-                    ('-', '+')
-                };
-
-                writeln!(
-                    writer,
-                    "L\t{}\t{}\t{}\t+\t{}M",
-                    cocktail::kmer::cannonical(predecessor, k),
-                    pred_sign.0,
-                    cano,
-                    k - ovl_len
-                )?;
-            }
-        }
+    for cano in solid.solid_kmers() {
+        for link in kmer_links(k, solid, cano) {
+            let link = if link.is_canonical() {
+                link
+            } else {
+                let reverse = link.reverse();
+                if kmer_links(k, solid, reverse.from.id).contains(&reverse) {
+                    continue;
+                }
 
-        if let Some((succs, ovl_len)) = solid.successors(cano) {
-            for successor in succs {
-                let succ_sign = if cocktail::kmer::parity_even(successor) {
-                    ('+', '-')
-                } else {
-                    ('-', '+')
-                };
-
-                writeln!(
-                    writer,
-                    "L\t{}\t+\t{}\t{}\t{}M",
-                    cano,
-                    cocktail::kmer::cannonical(successor, k),
-                    succ_sign.0,
-                    k - ovl_len
-                )?;
-            }
+                reverse
+            };
+
+            crate::graph::gfa::write_link(
+                writer,
+                version,
+                (link.from.id, link.from.orientation(), k as usize),
+                (link.to.id, link.to.orientation(), k as usize),
+                link.ovl_len,
+                k,
+                link.supported,
+            )?;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solid_kmers() {
+        let k = 3;
+        let seq = b"ACTGACGTTAGCCATTGACCAG";
+
+        let mut bitfield = bv::BitVec::new_fill(false, cocktail::kmer::get_hash_space_size(k));
+        let mut expected = Vec::new();
+        for subseq in seq.windows(k as usize) {
+            let cano = crate::kmer::cannonical(crate::kmer::seq2bit(subseq), k);
+
+            bitfield.set(crate::kmer::remove_first_bit(cano) as u64, true);
+            expected.push(cano);
+        }
+        expected.sort_unstable();
+        expected.dedup();
+
+        let dense = Graph::new(bitfield.into(), k, 1);
+        assert!(matches!(dense.solidity, Backend::Bitfield(_)));
+
+        assert_eq!(dense.solid_kmers().collect::<Vec<u128>>(), expected);
+    }
+
+    #[test]
+    fn kmer_gfa() {
+        let k = 7;
+        let counter = crate::counter::Counter::new(k, 8);
+        counter.add_sequence(crate::graph::fixtures::MAJOR);
+        counter.add_sequence(crate::graph::fixtures::MINOR);
+        counter.set(crate::kmer::seq2bit(b"GACTGAC"), 0);
+
+        let solid = Graph::new(counter.solidity(0), k, 2);
+
+        let mut output = Vec::new();
+        write_kmer_graph(&mut output, k, &solid, 1).unwrap();
+        let mut links: Vec<&str> = std::str::from_utf8(&output)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with('L'))
+            .collect();
+        links.sort_unstable();
+
+        let mut graph = crate::graph::unitig::Bidirected::new(k);
+        for cano in solid.solid_kmers() {
+            graph.add_node(cano, k as usize);
+            for link in kmer_links(k, &solid, cano) {
+                graph.add_link(link);
+            }
+        }
+
+        let mut expected = Vec::new();
+        crate::graph::unitig::write_gfa_links(&mut expected, &graph, 1).unwrap();
+        let mut expected: Vec<&str> = std::str::from_utf8(&expected).unwrap().lines().collect();
+        expected.sort_unstable();
+
+        assert_eq!(links, expected);
+        assert!(links.iter().any(|link| link.contains("\t5M")));
+    }
+}
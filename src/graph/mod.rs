@@ -20,5 +20,31 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
  */
 
+pub mod contig;
+pub mod gfa;
 pub mod kmer;
+pub mod path;
+pub mod simplify;
 pub mod unitig;
+
+#[cfg(test)]
+pub mod fixtures {
+    /* two variant of the same sequence, they differ by one base and build a bubble */
+    pub const MAJOR: &[u8] = b"TGGCAGGGCTTTTAGTCGTGACTGACGTTAGCCATTG";
+    pub const MINOR: &[u8] = b"TGGCAGGGCTTTTAGACGTGACTGACGTTAGCCATTG";
+
+    pub fn variant_graph(k: u8, nb_major: usize, nb_minor: usize) -> crate::graph::kmer::Graph {
+        let counter = crate::counter::Counter::new(k, 8);
+        for _ in 0..nb_major {
+            counter.add_sequence(MAJOR);
+        }
+        for _ in 0..nb_minor {
+            counter.add_sequence(MINOR);
+        }
+
+        let mut solid = crate::graph::kmer::Graph::new(counter.solidity(0), k, 1);
+        solid.set_abundance(counter);
+
+        solid
+    }
+}
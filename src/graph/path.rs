@@ -0,0 +1,546 @@
+/*
+Copyright (c) 2020 Pierre Marijon <pmarijon@mmci.uni-saarland.de>
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+
+/* project use */
+use crate::graph;
+use crate::graph::unitig::{Link, Side, Unitig};
+
+/* crate use */
+use anyhow::Result;
+use rayon::prelude::*;
+
+pub type Path = Vec<Side<usize>>;
+
+type Pairs = rustc_hash::FxHashMap<(Side<usize>, Side<usize>), u64>;
+
+/* copy of a repeat by (repeat id, neighbour side, neighbour is before the repeat) */
+type Copies = rustc_hash::FxHashMap<(usize, Side<usize>, bool), usize>;
+
+/* kmer present in more than one unitig are ambiguous and not indexed */
+pub struct KmerIndex {
+    k: u8,
+    index: rustc_hash::FxHashMap<u128, Option<(usize, bool)>>,
+}
+
+impl KmerIndex {
+    pub fn new(unitig_graph: &graph::unitig::UnitigGraph, solid: &graph::kmer::Graph) -> Self {
+        let k = unitig_graph.k;
+        let mut index = rustc_hash::FxHashMap::default();
+
+        for unitig in unitig_graph.unitigs.iter() {
+            for kmer in unitig.seq.windows(k as usize).map(crate::kmer::seq2bit) {
+                if !solid.is_solid(kmer) {
+                    continue;
+                }
+
+                let cano = crate::kmer::cannonical(kmer, k);
+                index
+                    .entry(cano)
+                    .and_modify(|value| {
+                        if *value != Some((unitig.id, kmer == cano)) {
+                            *value = None
+                        }
+                    })
+                    .or_insert(Some((unitig.id, kmer == cano)));
+            }
+        }
+
+        KmerIndex { k, index }
+    }
+
+    fn get(&self, kmer: u128) -> Option<Side<usize>> {
+        let cano = crate::kmer::cannonical(kmer, self.k);
+
+        self.index
+            .get(&cano)
+            .copied()
+            .flatten()
+            .map(|(id, forward)| Side::new(id, forward == (kmer == cano)))
+    }
+
+    /* a read path is split when two consecutive unitigs aren't linked */
+    pub fn thread(
+        &self,
+        seq: &[u8],
+        solid: &graph::kmer::Graph,
+        unitig_graph: &graph::unitig::UnitigGraph,
+    ) -> Vec<Path> {
+        let mut paths = Vec::new();
+        let mut path: Path = Vec::new();
+
+        for kmer in seq.windows(self.k as usize).map(crate::kmer::seq2bit) {
+            if !solid.is_solid(kmer) {
+                continue;
+            }
+
+            if let Some(side) = self.get(kmer) {
+                match path.last() {
+                    Some(last) if *last == side => continue,
+                    Some(last)
+                        if !unitig_graph
+                            .graph
+                            .successors(*last)
+                            .any(|link| link.to == side) =>
+                    {
+                        paths.push(std::mem::take(&mut path))
+                    }
+                    _ => (),
+                }
+
+                path.push(side);
+            }
+        }
+        paths.push(path);
+
+        paths.retain(|path| path.len() > 1);
+
+        paths
+    }
+}
+
+fn normalize(path: Path) -> Path {
+    let reverse: Path = path.iter().rev().map(|side| side.flip()).collect();
+
+    if reverse < path {
+        reverse
+    } else {
+        path
+    }
+}
+
+pub fn add_paths(
+    counts: &mut rustc_hash::FxHashMap<Path, u64>,
+    seqs: &[&[u8]],
+    index: &KmerIndex,
+    solid: &graph::kmer::Graph,
+    unitig_graph: &graph::unitig::UnitigGraph,
+) {
+    let paths: Vec<Path> = seqs
+        .par_iter()
+        .flat_map(|seq| index.thread(seq, solid, unitig_graph))
+        .collect();
+
+    for path in paths {
+        *counts.entry(normalize(path)).or_insert(0) += 1;
+    }
+}
+
+pub fn sorted_paths(counts: rustc_hash::FxHashMap<Path, u64>) -> Vec<(Path, u64)> {
+    let mut paths: Vec<(Path, u64)> = counts.into_iter().collect();
+    paths.sort();
+
+    paths
+}
+
+/* reads passing through a unitig give the (predecessor, successor) pairs of its forward side */
+fn repeat_pairs(paths: &[(Path, u64)]) -> rustc_hash::FxHashMap<usize, Pairs> {
+    let mut pairs: rustc_hash::FxHashMap<usize, Pairs> = rustc_hash::FxHashMap::default();
+
+    for (path, count) in paths {
+        for window in path.windows(3) {
+            let (before, middle, after) = (window[0], window[1], window[2]);
+            let pair = if middle.forward {
+                (before, after)
+            } else {
+                (after.flip(), before.flip())
+            };
+
+            *pairs.entry(middle.id).or_default().entry(pair).or_insert(0) += count;
+        }
+    }
+
+    pairs
+}
+
+/* each predecessor of the repeat must be paired by reads with exactly one successor, and reciprocally */
+fn repeat_matching(
+    unitig_graph: &graph::unitig::UnitigGraph,
+    id: usize,
+    pairs: &Pairs,
+    min_support: u64,
+) -> Option<Vec<(Link<usize>, Link<usize>)>> {
+    let side = Side::new(id, true);
+    let ins: Vec<Link<usize>> = unitig_graph.graph.predecessors(side).collect();
+    let outs: Vec<Link<usize>> = unitig_graph.graph.successors(side).copied().collect();
+
+    if ins.len() < 2
+        || ins.len() != outs.len()
+        || ins
+            .iter()
+            .chain(outs.iter())
+            .any(|link| link.from.id == link.to.id)
+    {
+        return None;
+    }
+
+    let mut matching = Vec::new();
+    for in_link in ins.iter() {
+        let supported: Vec<&Link<usize>> = outs
+            .iter()
+            .filter(|out_link| {
+                pairs
+                    .get(&(in_link.from, out_link.to))
+                    .copied()
+                    .unwrap_or(0)
+                    >= min_support
+            })
+            .collect();
+
+        if supported.len() != 1 {
+            return None;
+        }
+
+        matching.push((*in_link, *supported[0]));
+    }
+
+    let used: rustc_hash::FxHashSet<Side<usize>> =
+        matching.iter().map(|(_, out_link)| out_link.to).collect();
+    if used.len() != outs.len() {
+        return None;
+    }
+
+    Some(matching)
+}
+
+/* copy of a resolved repeat used by a path side, chosen by the neighbour before it in repeat
+orientation, or by the neighbour after it */
+fn copy_side(path: &[Side<usize>], i: usize, copies: &Copies) -> Option<Side<usize>> {
+    let side = path[i];
+    let before = i.checked_sub(1).map(|j| path[j]);
+    let after = path.get(i + 1).copied();
+    let (before, after) = if side.forward {
+        (before, after)
+    } else {
+        (after.map(|s| s.flip()), before.map(|s| s.flip()))
+    };
+
+    before
+        .and_then(|before| copies.get(&(side.id, before, true)))
+        .or_else(|| after.and_then(|after| copies.get(&(side.id, after, false))))
+        .map(|copy| Side::new(*copy, side.forward))
+}
+
+/* path sides follow copies then merged unitigs, a path is split when two consecutive sides aren't linked */
+fn translate_path(
+    path: &[Side<usize>],
+    copies: &Copies,
+    old2new: &rustc_hash::FxHashMap<usize, usize>,
+    duplicated: &graph::unitig::UnitigGraph,
+    positions: &[(Side<usize>, usize)],
+) -> Vec<Path> {
+    let mut subpaths = Vec::new();
+    let mut subpath: Path = Vec::new();
+    for i in 0..path.len() {
+        /* resolved repeats aren't in the duplicated graph */
+        let side = if old2new.contains_key(&path[i].id) {
+            Some(path[i])
+        } else {
+            copy_side(path, i, copies)
+        };
+
+        match side.map(|side| Side::new(old2new[&side.id], side.forward)) {
+            None => subpaths.push(std::mem::take(&mut subpath)),
+            Some(side) => {
+                if let Some(last) = subpath.last() {
+                    if !duplicated
+                        .graph
+                        .successors(*last)
+                        .any(|link| link.to == side)
+                    {
+                        subpaths.push(std::mem::take(&mut subpath));
+                    }
+                }
+
+                subpath.push(side);
+            }
+        }
+    }
+    subpaths.push(subpath);
+
+    let mut paths = Vec::new();
+    for subpath in subpaths {
+        let mut path: Path = Vec::new();
+        let mut last: Option<(Side<usize>, usize)> = None;
+
+        for side in subpath {
+            let (new, pos) = positions[side.id];
+            let new = Side::new(new.id, new.forward == side.forward);
+
+            /* next unitig of the same merged chain */
+            if let Some((last_side, last_pos)) = last {
+                let step = if new.forward {
+                    last_pos + 1
+                } else {
+                    last_pos.wrapping_sub(1)
+                };
+                if last_side == new && pos == step {
+                    last = Some((new, pos));
+                    continue;
+                }
+            }
+
+            path.push(new);
+            last = Some((new, pos));
+        }
+
+        if path.len() > 1 {
+            paths.push(path);
+        }
+    }
+
+    paths
+}
+
+/* a resolved repeat is replaced by one copy per pair of neighbours, neighbours of a resolved repeat are not resolved in the same pass, chains created by copies are merged */
+pub fn resolve_repeats(
+    unitig_graph: &graph::unitig::UnitigGraph,
+    solid: &graph::kmer::Graph,
+    paths: &[(Path, u64)],
+    min_support: u64,
+) -> (graph::unitig::UnitigGraph, Vec<(Path, u64)>, usize) {
+    let pairs = repeat_pairs(paths);
+
+    let mut resolved = std::collections::BTreeMap::new();
+    let mut touched = rustc_hash::FxHashSet::default();
+    for id in 0..unitig_graph.unitigs.len() {
+        if touched.contains(&id) {
+            continue;
+        }
+
+        if let Some(matching) = pairs
+            .get(&id)
+            .and_then(|pairs| repeat_matching(unitig_graph, id, pairs, min_support))
+        {
+            touched.insert(id);
+            for (in_link, out_link) in matching.iter() {
+                touched.insert(in_link.from.id);
+                touched.insert(out_link.to.id);
+            }
+
+            resolved.insert(id, matching);
+        }
+    }
+
+    if resolved.is_empty() {
+        return (
+            graph::unitig::UnitigGraph::from_links(
+                unitig_graph.k,
+                unitig_graph.unitigs.iter().cloned(),
+                unitig_graph.links(),
+            ),
+            paths.to_vec(),
+            0,
+        );
+    }
+
+    let mut unitigs: Vec<Unitig> = unitig_graph
+        .unitigs
+        .iter()
+        .filter(|unitig| !resolved.contains_key(&unitig.id))
+        .cloned()
+        .collect();
+
+    let mut links: Vec<Link<usize>> = unitig_graph
+        .links()
+        .into_iter()
+        .filter(|link| !resolved.contains_key(&link.from.id) && !resolved.contains_key(&link.to.id))
+        .collect();
+
+    let mut copies: Copies = rustc_hash::FxHashMap::default();
+    let mut next_id = unitig_graph.unitigs.len();
+    for (id, matching) in resolved.iter() {
+        for (in_link, out_link) in matching {
+            let mut copy = unitig_graph.unitigs[*id].clone();
+            copy.id = next_id;
+
+            let side = Side::new(next_id, true);
+            links.push(Link::new(
+                in_link.from,
+                side,
+                in_link.ovl_len,
+                in_link.supported,
+            ));
+            links.push(Link::new(
+                side,
+                out_link.to,
+                out_link.ovl_len,
+                out_link.supported,
+            ));
+            copies.insert((*id, in_link.from, true), next_id);
+            copies.insert((*id, out_link.to, false), next_id);
+
+            unitigs.push(copy);
+            next_id += 1;
+        }
+    }
+
+    let old2new: rustc_hash::FxHashMap<usize, usize> = unitigs
+        .iter()
+        .enumerate()
+        .map(|(new_id, unitig)| (unitig.id, new_id))
+        .collect();
+    let duplicated = graph::unitig::UnitigGraph::from_links(unitig_graph.k, unitigs, links);
+    let (compacted, positions) = duplicated.compact(solid);
+
+    let mut counts = rustc_hash::FxHashMap::default();
+    for (path, count) in paths {
+        for path in translate_path(path, &copies, &old2new, &duplicated, &positions) {
+            *counts.entry(normalize(path)).or_insert(0) += count;
+        }
+    }
+
+    (compacted, sorted_paths(counts), resolved.len())
+}
+
+pub fn path2string(path: &[Side<usize>], separator: &str) -> String {
+    path.iter()
+        .map(|side| format!("{}{}", side.id, side.orientation()))
+        .collect::<Vec<String>>()
+        .join(separator)
+}
+
+pub fn write_tsv<W>(writer: &mut W, paths: &[(Path, u64)]) -> Result<()>
+where
+    W: std::io::Write,
+{
+    for (path, count) in paths {
+        writeln!(writer, "{}\t{}", path2string(path, ","), count)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_gfa<W>(writer: &mut W, paths: &[(Path, u64)], version: u8) -> Result<()>
+where
+    W: std::io::Write,
+{
+    for (id, (path, count)) in paths.iter().enumerate() {
+        if version == 2 {
+            writeln!(
+                writer,
+                "O\tpath{}\t{}\tRC:i:{}",
+                id,
+                path2string(path, " "),
+                count
+            )?;
+        } else {
+            writeln!(
+                writer,
+                "P\tpath{}\t{}\t*\tRC:i:{}",
+                id,
+                path2string(path, ","),
+                count
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread() {
+        let k = 7;
+        let major = graph::fixtures::MAJOR;
+        let minor = graph::fixtures::MINOR;
+
+        let solid = graph::fixtures::variant_graph(k, 1, 1);
+        let unitig_graph = graph::unitig::UnitigGraph::new(k, &solid);
+        let index = KmerIndex::new(&unitig_graph, &solid);
+
+        let mut counts = rustc_hash::FxHashMap::default();
+        add_paths(
+            &mut counts,
+            &[major, minor, &bio::alphabets::dna::revcomp(major)],
+            &index,
+            &solid,
+            &unitig_graph,
+        );
+
+        let paths = sorted_paths(counts);
+        assert_eq!(paths.len(), 2);
+        assert!(paths.iter().all(|(path, _)| path.len() == 3));
+
+        let mut nb_reads: Vec<u64> = paths.iter().map(|x| x.1).collect();
+        nb_reads.sort_unstable();
+        assert_eq!(nb_reads, vec![1, 2]);
+    }
+
+    #[test]
+    fn resolve() {
+        let k = 9;
+        let seq: &[u8] = b"ACGCGCGCTAAGGCAAGGTGGCGCGGGGTATCAGCTGCAACGCGAAGGTGGCGCGGGGTAGAGCTGGTGTGTTACCGTAATGCCTTTCCCTAACAGAG";
+        let variant: &[u8] = b"ACGCGCGCTAAGGCAAGGTGGCGCGGGGTATCAGCTGCAACGCGAAGGTGGCGCGGGGTAGAGCTGGTGTGTTACCGTAACGCCTTTCCCTAACAGAG";
+
+        let counter = crate::counter::Counter::new(k, 8);
+        counter.add_sequence(seq);
+        counter.add_sequence(variant);
+
+        let solid = graph::kmer::Graph::new(counter.solidity(0), k, 1);
+        let unitig_graph = graph::unitig::UnitigGraph::new(k, &solid);
+        assert_eq!(unitig_graph.unitigs.len(), 7);
+
+        let index = KmerIndex::new(&unitig_graph, &solid);
+        let mut counts = rustc_hash::FxHashMap::default();
+        add_paths(&mut counts, &[seq, variant], &index, &solid, &unitig_graph);
+        let paths = sorted_paths(counts);
+
+        let (resolved, resolved_paths, nb_repeat) =
+            resolve_repeats(&unitig_graph, &solid, &paths, 3);
+        assert_eq!(nb_repeat, 0);
+        assert_eq!(resolved.unitigs.len(), 7);
+        assert_eq!(resolved_paths, paths);
+
+        /* repeat copies are merged with their neighbours until the variant */
+        let (resolved, resolved_paths, nb_repeat) =
+            resolve_repeats(&unitig_graph, &solid, &paths, 2);
+        assert_eq!(nb_repeat, 1);
+        assert_eq!(resolved.unitigs.len(), 4);
+        assert!(resolved.unitigs.iter().all(|unitig| {
+            unitig.gaps.iter().map(|deep| *deep as usize).sum::<usize>() + k as usize
+                == unitig.seq.len()
+        }));
+
+        let begin = &seq[..75];
+        assert!(resolved.unitigs.iter().any(|unitig| {
+            unitig.seq.starts_with(begin)
+                || unitig.seq.ends_with(&bio::alphabets::dna::revcomp(begin))
+        }));
+
+        /* each read follow its own branch after copies */
+        assert_eq!(resolved_paths.len(), 2);
+        for (path, count) in resolved_paths.iter() {
+            assert_eq!(path.len(), 3);
+            assert_eq!(*count, 1);
+
+            for window in path.windows(2) {
+                assert!(resolved
+                    .graph
+                    .successors(window[0])
+                    .any(|link| link.to == window[1]));
+            }
+        }
+    }
+}
@@ -0,0 +1,423 @@
+/*
+Copyright (c) 2020 Pierre Marijon <pmarijon@mmci.uni-saarland.de>
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+
+/* project use */
+use crate::graph;
+
+/* crate use */
+use anyhow::Result;
+use rayon::prelude::*;
+
+struct Tip {
+    attach: u128,
+    seq: Vec<u8>,
+}
+
+/* tip is orient from the branching kmer to the dead end */
+fn as_tip(seq: &[u8], k: u8, solid: &graph::kmer::Graph) -> Option<Tip> {
+    let k_len = k as usize;
+    if seq.len() <= k_len {
+        return None;
+    }
+
+    let first = crate::kmer::seq2bit(&seq[..k_len]);
+    let last = crate::kmer::seq2bit(&seq[seq.len() - k_len..]);
+
+    let seq = match (
+        solid.predecessors(first).is_none(),
+        solid.successors(last).is_none(),
+    ) {
+        (false, true) => seq.to_vec(),
+        (true, false) => bio::alphabets::dna::revcomp(seq),
+        _ => return None,
+    };
+
+    let dead = crate::kmer::seq2bit(&seq[seq.len() - k_len..]);
+    if solid.predecessors(dead).map_or(0, |(preds, _)| preds.len()) != 1 {
+        return None;
+    }
+
+    Some(Tip {
+        attach: crate::kmer::seq2bit(&seq[..k_len]),
+        seq,
+    })
+}
+
+fn find_tips(solid: &graph::kmer::Graph, max_len: usize) -> Vec<Tip> {
+    let k = solid.k();
+
+    let mut attach2tips: rustc_hash::FxHashMap<u128, Vec<Tip>> = rustc_hash::FxHashMap::default();
+    for tip in graph::unitig::build_unitigs(k, solid)
+        .par_iter()
+        .filter(|unitig| unitig.seq.len() < max_len)
+        .filter_map(|unitig| as_tip(&unitig.seq, k, solid))
+        .collect::<Vec<Tip>>()
+    {
+        attach2tips.entry(tip.attach).or_default().push(tip);
+    }
+
+    let mut tips = Vec::new();
+    for (attach, mut siblings) in attach2tips {
+        let nb_branch = solid.successors(attach).map_or(0, |(succs, _)| succs.len());
+
+        /* if all branch are tips, the longest one is keep */
+        if siblings.len() >= nb_branch {
+            siblings.sort_by(|a, b| (a.seq.len(), &a.seq).cmp(&(b.seq.len(), &b.seq)));
+            siblings.pop();
+        }
+
+        tips.extend(siblings);
+    }
+
+    tips
+}
+
+pub fn clip_tips(solid: &mut graph::kmer::Graph, max_len: usize) -> usize {
+    let k = solid.k() as usize;
+    let mut nb_tip = 0;
+
+    loop {
+        let tips = find_tips(solid, max_len);
+        if tips.is_empty() {
+            break;
+        }
+
+        nb_tip += tips.len();
+        solid.remove_kmers(
+            tips.iter()
+                .flat_map(|tip| tip.seq.windows(k).skip(1).map(crate::kmer::seq2bit)),
+        );
+    }
+
+    nb_tip
+}
+
+pub struct Bubble {
+    pub kept: graph::unitig::Unitig,
+    pub popped: Vec<graph::unitig::Unitig>,
+}
+
+pub fn coverage(unitig: &graph::unitig::Unitig) -> f64 {
+    unitig.abundance.as_ref().map_or(0.0, |a| a.mean)
+}
+
+/* branches of a bubble go from the same side of the first end kmer to the same side of the last one */
+fn bubble_ends(unitig: &graph::unitig::Unitig, k: u8) -> (u128, u128) {
+    let forward = (unitig.first_kmer(true, k), unitig.last_kmer(true, k));
+    let reverse = (unitig.first_kmer(false, k), unitig.last_kmer(false, k));
+
+    forward.min(reverse)
+}
+
+fn similar_length(a: &graph::unitig::Unitig, b: &graph::unitig::Unitig, length_ratio: f64) -> bool {
+    let (short, long) = if a.seq.len() < b.seq.len() {
+        (a.seq.len(), b.seq.len())
+    } else {
+        (b.seq.len(), a.seq.len())
+    };
+
+    short as f64 >= length_ratio * long as f64
+}
+
+fn find_bubbles(solid: &graph::kmer::Graph, length_ratio: f64) -> Vec<Bubble> {
+    let k = solid.k();
+    let mut ends2tigs: std::collections::BTreeMap<(u128, u128), Vec<graph::unitig::Unitig>> =
+        std::collections::BTreeMap::new();
+
+    for unitig in graph::unitig::build_unitigs(k, solid) {
+        if unitig.circular {
+            continue;
+        }
+
+        ends2tigs
+            .entry(bubble_ends(&unitig, k))
+            .or_default()
+            .push(unitig);
+    }
+
+    let mut bubbles = Vec::new();
+    for (_, mut branches) in ends2tigs {
+        if branches.len() < 2 {
+            continue;
+        }
+
+        branches.sort_by(|a, b| {
+            coverage(a)
+                .partial_cmp(&coverage(b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.seq.len().cmp(&b.seq.len()))
+                .then(a.seq.cmp(&b.seq))
+        });
+
+        let kept = branches.pop().unwrap();
+        let popped: Vec<graph::unitig::Unitig> = branches
+            .into_iter()
+            .filter(|branch| similar_length(&kept, branch, length_ratio))
+            .collect();
+
+        if !popped.is_empty() {
+            bubbles.push(Bubble { kept, popped });
+        }
+    }
+
+    bubbles
+}
+
+/* end kmer of a branch are share with flanking unitigs */
+fn interior_kmers(seq: &[u8], k: usize) -> impl Iterator<Item = u128> + '_ {
+    seq.windows(k)
+        .skip(1)
+        .take((seq.len() + 1).saturating_sub(k + 2))
+        .map(crate::kmer::seq2bit)
+}
+
+pub fn pop_bubbles(solid: &mut graph::kmer::Graph, length_ratio: f64) -> Vec<Bubble> {
+    let k = solid.k() as usize;
+
+    if !solid.has_abundance() {
+        warn!("Kmer abundance isn't available, kept bubble branch is the longest");
+    }
+
+    let mut popped = Vec::new();
+    loop {
+        let bubbles: Vec<Bubble> = find_bubbles(solid, length_ratio)
+            .into_iter()
+            .filter(|bubble| bubble.popped.iter().any(|tig| tig.seq.len() > k + 1))
+            .collect();
+
+        if bubbles.is_empty() {
+            break;
+        }
+
+        solid.remove_kmers(
+            bubbles
+                .iter()
+                .flat_map(|bubble| bubble.popped.iter())
+                .flat_map(|tig| interior_kmers(&tig.seq, k)),
+        );
+
+        popped.extend(bubbles);
+    }
+
+    popped
+}
+
+pub fn write_bubbles<W>(writer: &mut W, bubbles: &[Bubble]) -> Result<()>
+where
+    W: std::io::Write,
+{
+    for (id, bubble) in bubbles.iter().enumerate() {
+        for (status, tig) in std::iter::once(("kept", &bubble.kept))
+            .chain(bubble.popped.iter().map(|tig| ("popped", tig)))
+        {
+            writeln!(
+                writer,
+                ">{}_{} LN:i:{} km:f:{:.3}",
+                id,
+                status,
+                tig.seq.len(),
+                coverage(tig)
+            )?;
+            writer.write_all(&tig.seq)?;
+            writer.write_all(b"\n")?;
+        }
+    }
+
+    Ok(())
+}
+
+/* end kmer shared with another unitig are keep */
+fn removable_kmers(unitig_graph: &graph::unitig::UnitigGraph, id: usize) -> Vec<u128> {
+    let k = unitig_graph.k;
+    let shared = |forward| {
+        unitig_graph
+            .graph
+            .successors(graph::unitig::Side::new(id, forward))
+            .any(|link| link.ovl_len == k)
+    };
+
+    let mut kmers: Vec<u128> = unitig_graph.unitigs[id]
+        .seq
+        .windows(k as usize)
+        .map(crate::kmer::seq2bit)
+        .collect();
+
+    if shared(true) {
+        kmers.pop();
+    }
+    if shared(false) && !kmers.is_empty() {
+        kmers.remove(0);
+    }
+
+    kmers
+}
+
+pub fn remove_low_coverage(
+    solid: &mut graph::kmer::Graph,
+    min_coverage: Option<f64>,
+    relative_coverage: Option<f64>,
+) -> usize {
+    if !solid.has_abundance() {
+        warn!("Kmer abundance isn't available, low coverage unitigs can't be removed");
+        return 0;
+    }
+
+    let unitig_graph = graph::unitig::UnitigGraph::new(solid.k(), solid);
+
+    let low: Vec<usize> = unitig_graph
+        .unitigs
+        .par_iter()
+        .filter(|unitig| {
+            let mean = coverage(unitig);
+
+            if let Some(min) = min_coverage {
+                if mean < min {
+                    return true;
+                }
+            }
+
+            if let Some(fraction) = relative_coverage {
+                let neighbors: std::collections::BTreeSet<usize> = [true, false]
+                    .iter()
+                    .flat_map(|forward| {
+                        unitig_graph
+                            .graph
+                            .successors(graph::unitig::Side::new(unitig.id, *forward))
+                    })
+                    .map(|link| link.to.id)
+                    .filter(|id| *id != unitig.id)
+                    .collect();
+
+                if !neighbors.is_empty() {
+                    let neighbors_mean = neighbors
+                        .iter()
+                        .map(|id| coverage(&unitig_graph.unitigs[*id]))
+                        .sum::<f64>()
+                        / neighbors.len() as f64;
+
+                    return mean < fraction * neighbors_mean;
+                }
+            }
+
+            false
+        })
+        .map(|unitig| unitig.id)
+        .collect();
+
+    solid.remove_kmers(
+        low.iter()
+            .flat_map(|id| removable_kmers(&unitig_graph, *id)),
+    );
+
+    low.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tip() {
+        let k = 7;
+        let main = b"TGGCAGGGCTTTTAGTCGTGACTGACGTTAGCCATTG";
+        let error = b"TGGCAGGGCTTTTAGACG";
+
+        let counter = crate::counter::Counter::new(k, 8);
+        counter.add_sequence(main);
+        counter.add_sequence(error);
+
+        let mut solid = graph::kmer::Graph::new(counter.solidity(0), k, 1);
+        assert_eq!(graph::unitig::build_unitigs(k, &solid).len(), 3);
+
+        assert_eq!(clip_tips(&mut solid, 10), 0);
+        assert_eq!(clip_tips(&mut solid, 11), 1);
+
+        let unitigs = graph::unitig::build_unitigs(k, &solid);
+        assert_eq!(unitigs.len(), 1);
+        assert_eq!(unitigs[0].seq.len(), main.len());
+    }
+
+    #[test]
+    fn low_coverage() {
+        let k = 7;
+        let build = || graph::fixtures::variant_graph(k, 10, 1);
+
+        let mut solid = build();
+        assert_eq!(remove_low_coverage(&mut solid, None, Some(0.5)), 1);
+        assert_eq!(graph::unitig::build_unitigs(k, &solid).len(), 1);
+
+        let mut solid = build();
+        assert_eq!(remove_low_coverage(&mut solid, Some(5.0), None), 1);
+        assert_eq!(graph::unitig::build_unitigs(k, &solid).len(), 1);
+
+        let mut solid = build();
+        assert_eq!(remove_low_coverage(&mut solid, Some(1.0), Some(0.1)), 0);
+    }
+
+    #[test]
+    fn bubble() {
+        let k = 7;
+        let major = graph::fixtures::MAJOR;
+
+        let mut solid = graph::fixtures::variant_graph(k, 2, 1);
+        assert_eq!(graph::unitig::build_unitigs(k, &solid).len(), 4);
+
+        let bubbles = pop_bubbles(&mut solid, 0.8);
+        assert_eq!(bubbles.len(), 1);
+        assert_eq!(bubbles[0].popped.len(), 1);
+
+        let unitigs = graph::unitig::build_unitigs(k, &solid);
+        assert_eq!(unitigs.len(), 1);
+        assert!(unitigs[0].seq == major || unitigs[0].seq == bio::alphabets::dna::revcomp(major));
+    }
+
+    #[test]
+    fn not_bubble() {
+        let k = 7;
+        let seq = b"CTCGGGAGACTTCGTCGAGTAGTTTGAGTCCAGTTACAATTATTCGTCGGCCCTGGTGCTGGCTGGGAGGCCCATTGGGTTGAGTCACATGGATGT";
+        let reverse_branch = b"TTGAGTCCAGTTACAATTATTCGTCG";
+
+        let build = || {
+            let counter = crate::counter::Counter::new(k, 8);
+            counter.add_sequence(seq);
+
+            let mut solid = graph::kmer::Graph::new(counter.solidity(0), k, 1);
+            solid.set_abundance(counter);
+
+            solid
+        };
+
+        let mut solid = build();
+        assert_eq!(graph::unitig::build_unitigs(k, &solid).len(), 7);
+        assert!(pop_bubbles(&mut solid, 0.8).is_empty());
+        assert_eq!(graph::unitig::build_unitigs(k, &solid).len(), 7);
+
+        let mut solid = build();
+        let bubbles = pop_bubbles(&mut solid, 0.0);
+        assert_eq!(bubbles.len(), 1);
+        assert_eq!(bubbles[0].popped.len(), 1);
+        for tig in std::iter::once(&bubbles[0].kept).chain(bubbles[0].popped.iter()) {
+            assert_ne!(tig.seq, reverse_branch.to_vec());
+            assert_ne!(tig.seq, bio::alphabets::dna::revcomp(&reverse_branch[..]));
+        }
+    }
+}
@@ -26,268 +26,1213 @@ use crate::utils;
 
 /* crate use */
 use anyhow::Result;
+use rayon::prelude::*;
 
-#[derive(Debug, PartialEq, PartialOrd, Clone, Hash)]
-pub enum Edge {
-    Kmer,
-    Begin,
-    End,
-    Both,
+/* standard use */
+use std::borrow::Borrow;
+
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy, Hash, Eq, Ord)]
+pub struct Side<T> {
+    pub id: T,
+    pub forward: bool,
+}
+
+impl<T: Copy> Side<T> {
+    pub fn new(id: T, forward: bool) -> Self {
+        Side { id, forward }
+    }
+
+    pub fn flip(&self) -> Self {
+        Side {
+            id: self.id,
+            forward: !self.forward,
+        }
+    }
+
+    pub fn orientation(&self) -> char {
+        if self.forward {
+            '+'
+        } else {
+            '-'
+        }
+    }
+}
+
+/* the end of from side overlap the begin of to side on ovl_len bases */
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy, Hash, Eq, Ord)]
+pub struct Link<T> {
+    pub from: Side<T>,
+    pub to: Side<T>,
+    pub ovl_len: u8,
+    pub supported: bool,
+}
+
+impl<T: Copy + Ord> Link<T> {
+    pub fn new(from: Side<T>, to: Side<T>, ovl_len: u8, supported: bool) -> Self {
+        Link {
+            from,
+            to,
+            ovl_len,
+            supported,
+        }
+    }
+
+    pub fn reverse(&self) -> Self {
+        Link {
+            from: self.to.flip(),
+            to: self.from.flip(),
+            ovl_len: self.ovl_len,
+            supported: self.supported,
+        }
+    }
+
+    pub fn is_canonical(&self) -> bool {
+        *self <= self.reverse()
+    }
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Clone, Hash, Copy, Eq, Ord)]
-pub enum Node {
-    Tig(Tig),
-    Kmer(Kmer),
+pub struct Bidirected<T> {
+    pub k: u8,
+    lens: std::collections::BTreeMap<T, usize>,
+    adjacency: std::collections::BTreeMap<Side<T>, std::collections::BTreeSet<Link<T>>>,
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Clone, Hash, Copy, Eq, Ord)]
-pub struct Tig {
+impl<T: Copy + Ord> Bidirected<T> {
+    pub fn new(k: u8) -> Self {
+        Bidirected {
+            k,
+            lens: std::collections::BTreeMap::new(),
+            adjacency: std::collections::BTreeMap::new(),
+        }
+    }
+
+    pub fn add_node(&mut self, id: T, len: usize) {
+        self.lens.insert(id, len);
+    }
+
+    pub fn node_len(&self, id: T) -> usize {
+        self.lens[&id]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (T, usize)> + '_ {
+        self.lens.iter().map(|(id, len)| (*id, *len))
+    }
+
+    /* a link is always store with its reverse complement */
+    pub fn add_link(&mut self, link: Link<T>) {
+        let reverse = link.reverse();
+
+        self.adjacency.entry(link.from).or_default().insert(link);
+        self.adjacency
+            .entry(reverse.from)
+            .or_default()
+            .insert(reverse);
+    }
+
+    pub fn successors(&self, side: Side<T>) -> impl Iterator<Item = &Link<T>> + '_ {
+        self.adjacency.get(&side).into_iter().flatten()
+    }
+
+    pub fn predecessors(&self, side: Side<T>) -> impl Iterator<Item = Link<T>> + '_ {
+        self.successors(side.flip()).map(|link| link.reverse())
+    }
+
+    pub fn links(&self) -> impl Iterator<Item = &Link<T>> + '_ {
+        self.adjacency
+            .values()
+            .flatten()
+            .filter(|link| link.is_canonical())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Unitig {
     pub id: usize,
-    pub len: usize,
+    pub seq: Vec<u8>,
+    pub begin: u128,
+    pub end: u128,
     pub circular: bool,
+    pub gaps: Vec<u8>,
+    pub jumps: Vec<Jump>,
+    pub abundance: Option<Abundance>,
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Clone, Hash, Copy, Eq, Ord)]
-pub struct Kmer {
-    pub id: u64,
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jump {
+    pub pos: usize,
+    pub deep: u8,
+    pub supported: bool,
 }
 
-fn build_link(
-    s1: Node,
-    t1: Node,
-    s2: Node,
-    t2: Node,
-    graph: &petgraph::graphmap::UnGraphMap<Node, Edge>,
-) -> Option<(usize, char, usize, char)> {
-    if let Node::Tig(first) = s1 {
-        if let Node::Tig(second) = t2 {
-            if let Some(e1) = graph.edge_weight(s1, t1) {
-                if let Some(e2) = graph.edge_weight(s2, t2) {
-                    if e1 == &Edge::Begin && e2 == &Edge::Begin {
-                        Some((first.id, '-', second.id, '+'))
-                    } else if e1 == &Edge::Begin && e2 == &Edge::End {
-                        Some((first.id, '-', second.id, '-'))
-                    } else if e1 == &Edge::End && e2 == &Edge::Begin {
-                        Some((first.id, '+', second.id, '+'))
-                    } else if e1 == &Edge::End && e2 == &Edge::End {
-                        Some((first.id, '+', second.id, '-'))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            } else {
-                None
+#[derive(Debug, Clone, PartialEq)]
+pub struct Abundance {
+    pub sum: u64,
+    pub mean: f64,
+    pub median: u8,
+}
+
+impl Abundance {
+    fn new(seq: &[u8], k: u8, solid: &graph::kmer::Graph) -> Option<Self> {
+        if !solid.has_abundance() {
+            return None;
+        }
+
+        let mut values: Vec<u8> = seq
+            .windows(k as usize)
+            .map(crate::kmer::seq2bit)
+            .filter(|kmer| solid.is_solid(*kmer))
+            .filter_map(|kmer| solid.abundance(kmer))
+            .collect();
+
+        if values.is_empty() {
+            return None;
+        }
+
+        values.sort_unstable();
+        let sum: u64 = values.iter().map(|x| *x as u64).sum();
+
+        Some(Abundance {
+            sum,
+            mean: sum as f64 / values.len() as f64,
+            median: values[values.len() / 2],
+        })
+    }
+}
+
+impl Unitig {
+    /* unitig is orient to get the same record whatever the seed kmer */
+    fn new(
+        id: usize,
+        tig: std::collections::VecDeque<u8>,
+        begin: u128,
+        end: u128,
+        gaps: std::collections::VecDeque<u8>,
+        solid: &graph::kmer::Graph,
+    ) -> Self {
+        let forward: Vec<u8> = tig.into_iter().collect();
+        let reverse = bio::alphabets::dna::revcomp(&forward);
+
+        let mut unitig = if reverse < forward {
+            Unitig {
+                id,
+                seq: reverse,
+                begin: end,
+                end: begin,
+                circular: begin == end,
+                gaps: gaps.into_iter().rev().collect(),
+                jumps: Vec::new(),
+                abundance: None,
             }
         } else {
-            None
+            Unitig {
+                id,
+                seq: forward,
+                begin,
+                end,
+                circular: begin == end,
+                gaps: gaps.into_iter().collect(),
+                jumps: Vec::new(),
+                abundance: None,
+            }
+        };
+
+        unitig.jumps = Jump::from_gaps(&unitig.seq, &unitig.gaps, solid);
+        unitig.abundance = Abundance::new(&unitig.seq, solid.k(), solid);
+
+        unitig
+    }
+
+    pub fn first_kmer(&self, forward: bool, k: u8) -> u128 {
+        if forward {
+            crate::kmer::seq2bit(&self.seq[..k as usize])
+        } else {
+            crate::kmer::revcomp(
+                crate::kmer::seq2bit(&self.seq[self.seq.len() - k as usize..]),
+                k,
+            )
         }
-    } else {
-        None
+    }
+
+    pub fn last_kmer(&self, forward: bool, k: u8) -> u128 {
+        crate::kmer::revcomp(self.first_kmer(!forward, k), k)
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.jumps.iter().map(|j| j.deep as usize - 1).sum()
+    }
+
+    pub fn inferred(&self) -> usize {
+        self.jumps.iter().filter(|j| !j.supported).count()
     }
 }
 
-pub fn tig_kmer_tig(
-    graph: &petgraph::graphmap::UnGraphMap<Node, Edge>,
-) -> std::collections::HashSet<(usize, char, usize, char)> {
-    let mut ret = std::collections::HashSet::new();
-
-    for node in graph.nodes() {
-        if let Node::Tig(_) = node {
-            for nnode in graph.neighbors(node) {
-                if let Node::Kmer(_) = nnode {
-                    for nnnode in graph.neighbors(nnode) {
-                        if nnnode == node {
-                            continue;
-                        }
+impl Jump {
+    fn from_gaps(seq: &[u8], gaps: &[u8], solid: &graph::kmer::Graph) -> Vec<Self> {
+        let k = solid.k() as usize;
+        let mut jumps = Vec::new();
 
-                        if let Some(link) = build_link(node, nnode, nnode, nnnode, graph) {
-                            ret.insert(link);
-                        }
-                    }
-                }
+        let mut pos = 0;
+        for deep in gaps {
+            if *deep > 1 {
+                let kmer = crate::kmer::seq2bit(&seq[pos..pos + k]);
+                let next =
+                    crate::kmer::seq2bit(&seq[pos + *deep as usize..pos + *deep as usize + k]);
+
+                jumps.push(Jump {
+                    pos,
+                    deep: *deep,
+                    supported: solid.jump_support(kmer, next, *deep),
+                });
             }
+
+            pos += *deep as usize;
         }
-    }
 
-    ret
+        jumps
+    }
 }
 
-pub fn tig_kmer_kmer_tig(
-    graph: &petgraph::graphmap::UnGraphMap<Node, Edge>,
-) -> std::collections::HashSet<(usize, char, usize, char)> {
-    let mut ret = std::collections::HashSet::new();
-
-    for node in graph.nodes() {
-        if let Node::Tig(_) = node {
-            for nnode in graph.neighbors(node) {
-                if let Node::Kmer(_) = nnode {
-                    for nnnode in graph.neighbors(nnode) {
-                        if let Node::Kmer(_) = nnnode {
-                            for nnnnode in graph.neighbors(nnnode) {
-                                if nnnnode == node {
-                                    continue;
-                                }
-
-                                if let Some(link) = build_link(node, nnode, nnnode, nnnnode, graph)
-                                {
-                                    ret.insert(link);
-                                }
-                            }
-                        }
-                    }
-                }
+/* visited kmer only skip seeds, walks don't claim kmer: two threads with seeds in the same unitig
+can both build it before its kmer are mark, this duplicate work avoid synchronisation between walks */
+pub fn build_unitigs(k: u8, solid: &graph::kmer::Graph) -> Vec<Unitig> {
+    let visited = graph::kmer::Viewed::new(solid);
+
+    let mut unitigs: Vec<Unitig> = solid
+        .par_solid_kmers()
+        .filter_map(|kmer| {
+            if visited.contains(kmer) {
+                return None;
             }
+
+            utils::build_tig(kmer, k, solid, &visited)
+                .map(|(tig, begin, end, gaps)| Unitig::new(0, tig, begin, end, gaps, solid))
+        })
+        .collect();
+
+    /* sort and dedup remove duplicate unitigs and keep ids deterministic */
+    unitigs.par_sort_unstable_by(|a, b| a.seq.cmp(&b.seq));
+    unitigs.dedup_by(|a, b| a.seq == b.seq);
+
+    for (id, unitig) in unitigs.iter_mut().enumerate() {
+        unitig.id = id;
+    }
+
+    unitigs
+}
+
+/* stream of unitigs with the ids write in fasta and gfa, unitigs are build in parallel and yield in id order */
+pub struct Unitigs {
+    unitigs: std::vec::IntoIter<Unitig>,
+}
+
+impl Unitigs {
+    pub fn new(k: u8, solid: &graph::kmer::Graph) -> Self {
+        Unitigs {
+            unitigs: build_unitigs(k, solid).into_iter(),
         }
     }
+}
 
-    ret
+impl Iterator for Unitigs {
+    type Item = Unitig;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.unitigs.next()
+    }
 }
 
-pub fn write_unitig<W>(
-    fasta: &mut W,
-    gfa: &mut W,
-    k: u8,
-    solid: &graph::kmer::Graph,
-) -> Result<(
-    std::collections::HashMap<(u64, u64), Vec<usize>>,
-    petgraph::graphmap::UnGraphMap<Node, Edge>,
-)>
-where
-    W: std::io::Write,
-{
-    let mut tig_counter = 0;
-    let mut visited = graph::kmer::Viewed::new(cocktail::kmer::get_kmer_space_size(k), k);
-    let mut ends2tig: std::collections::HashMap<(u64, u64), Vec<usize>> =
-        std::collections::HashMap::new();
-    let mut unitig_graph = petgraph::graphmap::UnGraphMap::new();
-
-    for kmer in 0..cocktail::kmer::get_kmer_space_size(k) {
-        if !solid.is_solid(kmer) {
-            continue;
+pub struct UnitigGraph {
+    pub k: u8,
+    pub unitigs: Vec<Unitig>,
+    pub ends2tig: std::collections::HashMap<(u128, u128), Vec<usize>>,
+    pub graph: Bidirected<usize>,
+}
+
+impl UnitigGraph {
+    pub fn new(k: u8, solid: &graph::kmer::Graph) -> Self {
+        UnitigGraph::from_unitigs(k, solid, Unitigs::new(k, solid))
+    }
+
+    pub fn from_unitigs<I>(k: u8, solid: &graph::kmer::Graph, unitigs: I) -> Self
+    where
+        I: IntoIterator<Item = Unitig>,
+    {
+        let mut unitig_graph = UnitigGraph {
+            k,
+            unitigs: Vec::new(),
+            ends2tig: std::collections::HashMap::new(),
+            graph: Bidirected::new(k),
+        };
+
+        for unitig in unitigs {
+            unitig_graph.add_unitig(unitig);
         }
 
-        if visited.contains(kmer) {
-            continue;
+        unitig_graph.add_links(solid);
+
+        unitig_graph
+    }
+
+    /* unitigs are renumbered in order, links to a missing unitig are dropped */
+    pub fn from_links<I, L>(k: u8, unitigs: I, links: L) -> Self
+    where
+        I: IntoIterator<Item = Unitig>,
+        L: IntoIterator<Item = Link<usize>>,
+    {
+        let mut unitig_graph = UnitigGraph {
+            k,
+            unitigs: Vec::new(),
+            ends2tig: std::collections::HashMap::new(),
+            graph: Bidirected::new(k),
+        };
+
+        let mut old2new = rustc_hash::FxHashMap::default();
+        for (new_id, mut unitig) in unitigs.into_iter().enumerate() {
+            old2new.insert(unitig.id, new_id);
+            unitig.id = new_id;
+            unitig_graph.add_unitig(unitig);
         }
 
-        visited.insert(kmer);
-        if let Some((tig, begin, end)) = utils::build_tig(kmer, k, &solid, &mut visited) {
-            ends2tig
-                .entry(crate::utils::normalize_u64_2tuple((begin, end)))
-                .or_insert_with(Vec::new)
-                .push(tig_counter);
+        for link in links {
+            if let (Some(from), Some(to)) = (old2new.get(&link.from.id), old2new.get(&link.to.id)) {
+                unitig_graph.graph.add_link(Link::new(
+                    Side::new(*from, link.from.forward),
+                    Side::new(*to, link.to.forward),
+                    link.ovl_len,
+                    link.supported,
+                ));
+            }
+        }
 
-            let node_tig = graph::unitig::Node::Tig(graph::unitig::Tig {
-                id: tig_counter,
-                len: tig.len(),
-                circular: begin == end,
-            });
-            let node_begin = graph::unitig::Node::Kmer(graph::unitig::Kmer { id: begin });
-            let node_end = graph::unitig::Node::Kmer(graph::unitig::Kmer { id: end });
+        unitig_graph
+    }
 
-            //unitig_graph.add_node(node_tig);
+    fn add_unitig(&mut self, unitig: Unitig) {
+        self.ends2tig
+            .entry(utils::normalize_u128_2tuple((unitig.begin, unitig.end)))
+            .or_default()
+            .push(unitig.id);
 
-            //unitig_graph.add_node(node_begin);
+        self.graph.add_node(unitig.id, unitig.seq.len());
 
-            //unitig_graph.add_node(node_end);
+        self.unitigs.push(unitig);
+    }
 
-            if let Some(edge) = unitig_graph.edge_weight(node_tig, node_begin) {
-                if edge == &graph::unitig::Edge::Begin {
-                    //unitig_graph.add_edge(node_tig, node_begin, graph::unitig::Edge::Both);
-                }
-            } else {
-                //unitig_graph.add_edge(node_tig, node_begin, graph::unitig::Edge::Begin);
+    fn begins(&self) -> rustc_hash::FxHashMap<u128, Vec<Side<usize>>> {
+        let mut begins: rustc_hash::FxHashMap<u128, Vec<Side<usize>>> =
+            rustc_hash::FxHashMap::default();
+
+        for unitig in self.unitigs.iter() {
+            for forward in &[true, false] {
+                begins
+                    .entry(unitig.first_kmer(*forward, self.k))
+                    .or_default()
+                    .push(Side::new(unitig.id, *forward));
             }
+        }
+
+        begins
+    }
+
+    /* next unitigs begin by the last kmer of this side, or by one of its successors */
+    fn side_links(
+        &self,
+        solid: &graph::kmer::Graph,
+        begins: &rustc_hash::FxHashMap<u128, Vec<Side<usize>>>,
+        from: Side<usize>,
+    ) -> Vec<Link<usize>> {
+        let mut links = Vec::new();
+        let unitig = &self.unitigs[from.id];
+        let last = unitig.last_kmer(from.forward, self.k);
 
-            if let Some(edge) = unitig_graph.edge_weight(node_tig, node_end) {
-                if edge == &graph::unitig::Edge::End {
-                    //unitig_graph.add_edge(node_tig, node_end, graph::unitig::Edge::End);
+        for to in begins.get(&last).into_iter().flatten() {
+            if *to == from && unitig.seq.len() == self.k as usize {
+                continue;
+            }
+
+            links.push(Link::new(from, *to, self.k, true));
+        }
+
+        if let Some((succs, deep)) = solid.successors(last) {
+            for succ in succs {
+                for to in begins.get(&succ).into_iter().flatten() {
+                    links.push(Link::new(
+                        from,
+                        *to,
+                        self.k - deep,
+                        solid.jump_support(last, succ, deep),
+                    ));
                 }
+            }
+        }
+
+        links
+    }
+
+    fn add_links(&mut self, solid: &graph::kmer::Graph) {
+        let begins = self.begins();
+
+        let links: Vec<Link<usize>> = self
+            .unitigs
+            .par_iter()
+            .flat_map(|unitig| {
+                let mut links = self.side_links(solid, &begins, Side::new(unitig.id, true));
+                links.extend(self.side_links(solid, &begins, Side::new(unitig.id, false)));
+
+                links
+            })
+            .collect();
+
+        for link in links {
+            self.graph.add_link(link);
+        }
+
+        let nb_paralelle = self
+            .graph
+            .links()
+            .filter(|link| {
+                link.from.id != link.to.id
+                    && self.ends2tig[&utils::normalize_u128_2tuple((
+                        self.unitigs[link.from.id].begin,
+                        self.unitigs[link.from.id].end,
+                    ))]
+                        .contains(&link.to.id)
+            })
+            .count();
+
+        if nb_paralelle != 0 {
+            info!("{} links between parallel unitigs", nb_paralelle);
+        }
+    }
+
+    pub fn links(&self) -> Vec<Link<usize>> {
+        self.graph.links().copied().collect()
+    }
+
+    /* side reached by the only link of this side, if this link is the only one of the reached side */
+    fn single_successor(&self, side: Side<usize>) -> Option<Side<usize>> {
+        let mut succs = self.graph.successors(side);
+        let link = succs.next()?;
+
+        if succs.next().is_some()
+            || link.to.id == side.id
+            || self.graph.predecessors(link.to).count() != 1
+        {
+            return None;
+        }
+
+        Some(link.to)
+    }
+
+    fn chain(&self, id: usize) -> Vec<Side<usize>> {
+        let mut begin = Side::new(id, true);
+        while let Some(pred) = self.single_successor(begin.flip()).map(|side| side.flip()) {
+            /* a cycle of unitigs isn't merged */
+            if pred.id == id {
+                return vec![Side::new(id, true)];
+            }
+
+            begin = pred;
+        }
+
+        let mut chain = vec![begin];
+        while let Some(succ) = self.single_successor(*chain.last().unwrap()) {
+            if chain.iter().any(|side| side.id == succ.id) {
+                return vec![Side::new(id, true)];
+            }
+
+            chain.push(succ);
+        }
+
+        chain
+    }
+
+    /* merged unitig and if it keep the orientation of the chain */
+    fn merge(
+        &self,
+        id: usize,
+        chain: &[Side<usize>],
+        solid: &graph::kmer::Graph,
+    ) -> (Unitig, bool) {
+        let mut tig = std::collections::VecDeque::new();
+        let mut gaps = std::collections::VecDeque::new();
+
+        for (i, side) in chain.iter().enumerate() {
+            let unitig = &self.unitigs[side.id];
+            let (seq, side_gaps): (Vec<u8>, Vec<u8>) = if side.forward {
+                (unitig.seq.clone(), unitig.gaps.clone())
             } else {
-                //unitig_graph.add_edge(node_tig, node_end, graph::unitig::Edge::End);
+                (
+                    bio::alphabets::dna::revcomp(&unitig.seq),
+                    unitig.gaps.iter().rev().copied().collect(),
+                )
+            };
+
+            let ovl_len = if i == 0 {
+                0
+            } else {
+                let link = self
+                    .graph
+                    .successors(chain[i - 1])
+                    .next()
+                    .expect("chain sides are linked");
+                if link.ovl_len < self.k {
+                    gaps.push_back(self.k - link.ovl_len);
+                }
+
+                link.ovl_len as usize
+            };
+
+            tig.extend(seq[ovl_len..].iter());
+            gaps.extend(side_gaps);
+        }
+
+        let k = self.k as usize;
+        let seq: Vec<u8> = tig.iter().copied().collect();
+        let begin = crate::kmer::cannonical(crate::kmer::seq2bit(&seq[..k]), self.k);
+        let end = crate::kmer::cannonical(crate::kmer::seq2bit(&seq[seq.len() - k..]), self.k);
+
+        let unitig = Unitig::new(id, tig, begin, end, gaps, solid);
+        let forward = unitig.seq == seq;
+
+        (unitig, forward)
+    }
+
+    /* merge chains of unitigs linked by the only link of the two sides, for each old unitig the
+    new side of its forward side and its position in chain are returned */
+    pub fn compact(&self, solid: &graph::kmer::Graph) -> (UnitigGraph, Vec<(Side<usize>, usize)>) {
+        let mut positions = vec![None; self.unitigs.len()];
+        let mut unitigs = Vec::new();
+        let mut internals = rustc_hash::FxHashSet::default();
+
+        for unitig in self.unitigs.iter() {
+            if positions[unitig.id].is_some() {
+                continue;
             }
 
-            writeln!(
-                fasta,
-                ">{} LN:i:{} circular:Z:{} begin:i:{} end:i:{}",
-                tig_counter,
-                tig.len(),
-                begin == end,
-                begin,
-                end,
-            )?;
+            let id = unitigs.len();
+            let chain = self.chain(unitig.id);
+            if chain.len() == 1 {
+                let mut copy = unitig.clone();
+                copy.id = id;
+                unitigs.push(copy);
+                positions[unitig.id] = Some((Side::new(id, true), 0));
+                continue;
+            }
+
+            let (merged, forward) = self.merge(id, &chain, solid);
+            unitigs.push(merged);
+
+            for (i, side) in chain.iter().enumerate() {
+                positions[side.id] = Some((
+                    Side::new(id, side.forward == forward),
+                    if forward { i } else { chain.len() - 1 - i },
+                ));
+            }
+            for window in chain.windows(2) {
+                internals.insert((window[0], window[1]));
+                internals.insert((window[1].flip(), window[0].flip()));
+            }
+        }
+
+        let positions: Vec<(Side<usize>, usize)> = positions.into_iter().flatten().collect();
+        let new_side = |side: Side<usize>| {
+            let (new, _) = positions[side.id];
+            Side::new(new.id, new.forward == side.forward)
+        };
+
+        let links: Vec<Link<usize>> = self
+            .graph
+            .links()
+            .filter(|link| !internals.contains(&(link.from, link.to)))
+            .map(|link| {
+                Link::new(
+                    new_side(link.from),
+                    new_side(link.to),
+                    link.ovl_len,
+                    link.supported,
+                )
+            })
+            .collect();
+
+        (UnitigGraph::from_links(self.k, unitigs, links), positions)
+    }
+}
 
-            let (first_part, second_part) = tig.as_slices();
-            fasta.write_all(first_part)?;
-            fasta.write_all(second_part)?;
-            fasta.write_all(b"\n")?;
+pub fn write_fasta_record<W>(writer: &mut W, unitig: &Unitig) -> Result<()>
+where
+    W: std::io::Write,
+{
+    write!(
+        writer,
+        ">{} LN:i:{} circular:Z:{} begin:i:{} end:i:{}",
+        unitig.id,
+        unitig.seq.len(),
+        unitig.circular,
+        unitig.begin,
+        unitig.end,
+    )?;
 
-            write!(gfa, "S\t{}\t", tig_counter)?;
+    if let Some(abundance) = &unitig.abundance {
+        write!(
+            writer,
+            " KC:i:{} km:f:{:.3} dp:f:{:.3}",
+            abundance.sum, abundance.mean, abundance.mean
+        )?;
+    }
 
-            gfa.write_all(first_part)?;
-            gfa.write_all(second_part)?;
+    if !unitig.jumps.is_empty() {
+        graph::gfa::write_jump_tags(
+            writer,
+            ' ',
+            unitig.jumps.len(),
+            unitig.skipped(),
+            unitig.inferred(),
+        )?;
+    }
+    writer.write_all(b"\n")?;
 
-            writeln!(gfa, "\tLN:i:{}\tCI:Z:{}", tig.len(), begin == end,)?;
+    writer.write_all(&unitig.seq)?;
+    writer.write_all(b"\n")?;
 
-            tig_counter += 1;
+    Ok(())
+}
+
+pub fn write_gfa_segment<W>(writer: &mut W, unitig: &Unitig, version: u8) -> Result<()>
+where
+    W: std::io::Write,
+{
+    graph::gfa::write_segment(writer, version, unitig.id, &unitig.seq)?;
+    write!(
+        writer,
+        "\tLN:i:{}\tCI:Z:{}",
+        unitig.seq.len(),
+        unitig.circular
+    )?;
+
+    if let Some(abundance) = &unitig.abundance {
+        write!(
+            writer,
+            "\tKC:i:{}\tkm:f:{:.3}\tdp:f:{:.3}",
+            abundance.sum, abundance.mean, abundance.mean
+        )?;
+    }
+
+    if !unitig.jumps.is_empty() {
+        graph::gfa::write_jump_tags(
+            writer,
+            '\t',
+            unitig.jumps.len(),
+            unitig.skipped(),
+            unitig.inferred(),
+        )?;
+    }
+    writer.write_all(b"\n")?;
+
+    Ok(())
+}
+
+pub fn write_unitig<W, I>(fasta: &mut W, gfa: &mut W, unitigs: I, version: u8) -> Result<()>
+where
+    W: std::io::Write,
+    I: IntoIterator,
+    I::Item: Borrow<Unitig>,
+{
+    for unitig in unitigs {
+        write_fasta_record(fasta, unitig.borrow())?;
+        write_gfa_segment(gfa, unitig.borrow(), version)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_links<W>(gfa: &mut W, unitig_graph: &UnitigGraph, version: u8) -> Result<()>
+where
+    W: std::io::Write,
+{
+    write_gfa_links(gfa, &unitig_graph.graph, version)
+}
+
+pub fn write_gfa_links<W, T>(gfa: &mut W, graph: &Bidirected<T>, version: u8) -> Result<()>
+where
+    W: std::io::Write,
+    T: Copy + Ord + std::fmt::Display,
+{
+    for link in graph.links() {
+        graph::gfa::write_link(
+            gfa,
+            version,
+            (
+                link.from.id,
+                link.from.orientation(),
+                graph.node_len(link.from.id),
+            ),
+            (
+                link.to.id,
+                link.to.orientation(),
+                graph.node_len(link.to.id),
+            ),
+            link.ovl_len,
+            graph.k,
+            link.supported,
+        )?;
+    }
+
+    Ok(())
+}
+
+/* each side is a dot node, each link is write in both direction */
+pub fn write_dot<W, T>(writer: &mut W, graph: &Bidirected<T>) -> Result<()>
+where
+    W: std::io::Write,
+    T: Copy + Ord + std::fmt::Display,
+{
+    writeln!(writer, "digraph {{")?;
+
+    for (id, len) in graph.nodes() {
+        for orientation in &['+', '-'] {
+            writeln!(
+                writer,
+                "\t\"{}{}\" [label=\"{}{} {}\"];",
+                id, orientation, id, orientation, len
+            )?;
+        }
+    }
+
+    for link in graph.links() {
+        let reverse = link.reverse();
+
+        for l in std::iter::once(link).chain(if reverse != *link {
+            Some(&reverse)
         } else {
-            continue;
+            None
+        }) {
+            writeln!(
+                writer,
+                "\t\"{}{}\" -> \"{}{}\" [label=\"{}\"{}];",
+                l.from.id,
+                l.from.orientation(),
+                l.to.id,
+                l.to.orientation(),
+                l.ovl_len,
+                if l.supported { "" } else { ", style=dashed" }
+            )?;
         }
     }
 
-    Ok((ends2tig, unitig_graph))
+    writeln!(writer, "}}")?;
+
+    Ok(())
 }
 
-pub fn add_missing_edge(
-    solid: graph::kmer::Graph,
-    k: u8,
-    mut unitig_graph: petgraph::graphmap::UnGraphMap<Node, Edge>,
-) -> petgraph::graphmap::UnGraphMap<Node, Edge> {
-    let kmer_node: Vec<Node> = unitig_graph
-        .nodes()
-        .filter(|x| {
-            if let graph::unitig::Node::Kmer(_) = x {
-                true
-            } else {
-                false
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_solidity(seqs: &[&[u8]], k: u8) -> bv::BitVec<u8> {
+        let mut solidity = bv::BitVec::new_fill(false, cocktail::kmer::get_hash_space_size(k));
+
+        for seq in seqs {
+            for subseq in seq.windows(k as usize) {
+                let kmer = cocktail::kmer::seq2bit(subseq);
+                solidity.set(
+                    cocktail::kmer::remove_first_bit(cocktail::kmer::cannonical(kmer, k)),
+                    true,
+                );
             }
-        })
-        .collect();
+        }
 
-    for node in kmer_node {
-        if let graph::unitig::Node::Kmer(n) = node {
-            if let Some((succs, _)) = solid.successors(n.id) {
-                for succ in succs {
-                    let cano = cocktail::kmer::cannonical(succ, k);
-                    let node_succ = graph::unitig::Node::Kmer(graph::unitig::Kmer { id: cano });
-                    if unitig_graph.contains_node(node_succ) {
-                        /*unitig_graph.add_edge(
-                            graph::unitig::Node::Kmer(n),
-                            node_succ,
-                            graph::unitig::Edge::Kmer,
-                        );*/
-                    }
+        solidity
+    }
+
+    fn links(seqs: &[&[u8]], k: u8) -> (usize, usize, usize) {
+        let solid = graph::kmer::Graph::new(build_solidity(seqs, k).into(), k, 1);
+
+        let unitig_graph = UnitigGraph::new(k, &solid);
+        let links = unitig_graph.links();
+
+        /* two unitig ends sharing a kmer are linked only if their sequences overlap */
+        assert!(links.iter().all(|link| overlap_match(&unitig_graph, link)));
+
+        (
+            unitig_graph.unitigs.len(),
+            links.iter().filter(|link| link.ovl_len == k).count(),
+            links.iter().filter(|link| link.ovl_len < k).count(),
+        )
+    }
+
+    fn overlap_match(unitig_graph: &UnitigGraph, link: &Link<usize>) -> bool {
+        let ovl_len = link.ovl_len as usize;
+        let first = &unitig_graph.unitigs[link.from.id].seq;
+        let second = &unitig_graph.unitigs[link.to.id].seq;
+
+        let first_end = if link.from.forward {
+            first[first.len() - ovl_len..].to_vec()
+        } else {
+            bio::alphabets::dna::revcomp(&first[..ovl_len])
+        };
+
+        let second_begin = if link.to.forward {
+            second[..ovl_len].to_vec()
+        } else {
+            bio::alphabets::dna::revcomp(&second[second.len() - ovl_len..])
+        };
+
+        first_end == second_begin
+    }
+
+    #[test]
+    fn linear() {
+        assert_eq!(links(&[b"TGGGTAAAGGTGGCGCGGGG"], 7), (1, 0, 0));
+    }
+
+    #[test]
+    fn fork() {
+        assert_eq!(
+            links(&[b"TGGCAGGGCTTTTAGTCGTG", b"TGGCAGGGCTGGATGATCAG"], 7),
+            (3, 2, 0)
+        );
+    }
+
+    #[test]
+    fn cross() {
+        assert_eq!(
+            links(
+                &[b"TGTCGAGCCAGAAAACGACGGAAT", b"TAGATCAGCAGAAAACTTAAATGG"],
+                7
+            ),
+            (4, 0, 4)
+        );
+    }
+
+    #[test]
+    fn circular() {
+        let k = 7;
+        let plasmid = b"TGGGTAAAGGTGGCGCGGGGACTGACGTTAGC";
+        let mut seq = plasmid.to_vec();
+        seq.extend_from_slice(&plasmid[..k as usize - 1]);
+
+        let solid = graph::kmer::Graph::new(build_solidity(&[&seq], k).into(), k, 1);
+
+        let unitig_graph = UnitigGraph::new(k, &solid);
+        assert_eq!(unitig_graph.unitigs.len(), 1);
+
+        let unitig = &unitig_graph.unitigs[0];
+        assert!(unitig.circular);
+        assert_eq!(unitig.begin, unitig.end);
+        assert_eq!(unitig.seq.len(), plasmid.len() + k as usize);
+        assert_eq!(unitig_graph.links().len(), 1);
+
+        let rotation = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| build_unitigs(k, &solid)[0].seq.clone())
+        };
+        assert_eq!(rotation(1), rotation(4));
+        assert_eq!(Unitigs::new(k, &solid).count(), 1);
+    }
+
+    #[test]
+    fn cycle_closed_by_jump() {
+        let k = 7;
+        let cycle = b"TTTCCTCATGCAATTCAAAACCATGTCCGT";
+        let mut seq = cycle.to_vec();
+        seq.extend_from_slice(&cycle[..k as usize - 1]);
+        let mut entry = b"AATGTAGGCGAA".to_vec();
+        entry.extend_from_slice(&cycle[..15]);
+
+        let counter = crate::counter::Counter::new(k, 8);
+        counter.add_sequence(&seq);
+        counter.add_sequence(&entry);
+        /* last kmer of cycle is reach by a jump, first kmer of cycle have only entry as predecessor */
+        counter.set(crate::kmer::seq2bit(b"TTTTCCT"), 0);
+
+        let solid = graph::kmer::Graph::new(counter.solidity(0), k, 2);
+        let unitigs = build_unitigs(k, &solid);
+
+        assert!(!unitigs.is_empty());
+        assert!(unitigs
+            .iter()
+            .all(|unitig| unitig.seq.len() <= seq.len() + entry.len()));
+    }
+
+    fn paralelle_links(seqs: &[&[u8]], k: u8) -> usize {
+        let solid = graph::kmer::Graph::new(build_solidity(seqs, k).into(), k, 1);
+        let unitig_graph = UnitigGraph::new(k, &solid);
+
+        let paralelle = unitig_graph
+            .ends2tig
+            .values()
+            .find(|tigs| tigs.len() == 2)
+            .unwrap();
+
+        unitig_graph
+            .links()
+            .iter()
+            .filter(|link| paralelle.contains(&link.from.id) && paralelle.contains(&link.to.id))
+            .inspect(|link| assert!(overlap_match(&unitig_graph, link)))
+            .count()
+    }
+
+    #[test]
+    fn paralelle() {
+        let k = 7;
+
+        assert_eq!(
+            paralelle_links(
+                &[
+                    b"TGGCAGGGCTTTTAGTCGTGACTGACGTTAGCCATTG",
+                    b"TGGCAGGGCTTTTAGACGTGACTGACGTTAGCCATTG",
+                ],
+                k
+            ),
+            0
+        );
+
+        let repeat = b"ACGTTAGCCATTGACC";
+        let mut seq = b"TGGCAGGGCT".to_vec();
+        seq.extend_from_slice(repeat);
+        seq.extend_from_slice(b"GATCTAGG");
+        seq.extend_from_slice(repeat);
+        seq.extend_from_slice(b"TTCCGAAGT");
+
+        assert_eq!(paralelle_links(&[&seq], k), 2);
+    }
+
+    fn random_seq(state: &mut u64, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                *state ^= *state << 13;
+                *state ^= *state >> 7;
+                *state ^= *state << 17;
+
+                b"ACTG"[(*state % 4) as usize]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn symmetric_links() {
+        let k = 7;
+        let mut state = 42;
+
+        for _ in 0..50 {
+            let base = random_seq(&mut state, 80);
+            let mut variant = base.clone();
+            variant[40] = b"ACTG"[(state % 4) as usize];
+            let mut repeat = random_seq(&mut state, 20);
+            repeat.extend_from_slice(&base[10..30]);
+            repeat.extend(random_seq(&mut state, 20));
+
+            for (max_deep, reduction) in &[(1, false), (2, false), (2, true)] {
+                let mut solid = graph::kmer::Graph::new(
+                    build_solidity(&[&base, &variant, &repeat], k).into(),
+                    k,
+                    *max_deep,
+                );
+                if *reduction {
+                    solid.transitive_reduction();
                 }
-            }
 
-            if let Some((preds, _)) = solid.predecessors(n.id) {
-                for pred in preds {
-                    let cano = cocktail::kmer::cannonical(pred, k);
-                    let node_pred = graph::unitig::Node::Kmer(graph::unitig::Kmer { id: cano });
-                    if unitig_graph.contains_node(node_pred) {
-                        /*unitig_graph.add_edge(
-                            graph::unitig::Node::Kmer(n),
-                            node_pred,
-                            graph::unitig::Edge::Kmer,
-                        );*/
+                let unitig_graph = UnitigGraph::new(k, &solid);
+                let begins = unitig_graph.begins();
+
+                for unitig in unitig_graph.unitigs.iter() {
+                    for forward in &[true, false] {
+                        let side = Side::new(unitig.id, *forward);
+
+                        /* jump search depend on the direction, only plain successors are symmetric */
+                        if *max_deep == 1 {
+                            for link in unitig_graph.side_links(&solid, &begins, side) {
+                                assert!(unitig_graph
+                                    .side_links(&solid, &begins, link.to.flip())
+                                    .contains(&link.reverse()));
+                            }
+                        }
+
+                        for link in unitig_graph.graph.successors(side) {
+                            assert!(overlap_match(&unitig_graph, link));
+                            assert!(unitig_graph
+                                .graph
+                                .predecessors(link.to)
+                                .any(|pred| pred == *link));
+                        }
                     }
                 }
             }
         }
     }
 
-    unitig_graph
+    #[test]
+    fn linear_large_k() {
+        let seq = b"TGGGTAAAGGTGGCGCGGGGACTGACGTTAGCCATTGACCAGTAGGCA";
+        let k = 33;
+
+        let counter = crate::counter::Counter::new(k, 8);
+        counter.add_sequence(seq);
+
+        let solid = graph::kmer::Graph::new(counter.solidity(0), k, 1);
+        let unitig_graph = UnitigGraph::new(k, &solid);
+
+        assert_eq!(unitig_graph.unitigs.len(), 1);
+        assert_eq!(unitig_graph.unitigs[0].seq.len(), seq.len());
+    }
+
+    #[test]
+    fn deterministic_threads() {
+        let k = 7;
+        let solid = graph::kmer::Graph::new(
+            build_solidity(
+                &[
+                    b"TGTCGAGCCAGAAAACGACGGAAT",
+                    b"TAGATCAGCAGAAAACTTAAATGG",
+                    b"TGGCAGGGCTTTTAGTCGTG",
+                    b"TGGCAGGGCTGGATGATCAG",
+                ],
+                k,
+            )
+            .into(),
+            k,
+            1,
+        );
+
+        let run = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| {
+                    build_unitigs(k, &solid)
+                        .into_iter()
+                        .map(|u| (u.id, u.seq))
+                        .collect::<Vec<(usize, Vec<u8>)>>()
+                })
+        };
+
+        let sequential = run(1);
+        assert_eq!(sequential, run(4));
+
+        let streamed: Vec<(usize, Vec<u8>)> =
+            Unitigs::new(k, &solid).map(|u| (u.id, u.seq)).collect();
+        assert_eq!(sequential, streamed);
+
+        let mut from_stream = (Vec::new(), Vec::new());
+        write_unitig(
+            &mut from_stream.0,
+            &mut from_stream.1,
+            Unitigs::new(k, &solid),
+            1,
+        )
+        .unwrap();
+        let mut from_graph = (Vec::new(), Vec::new());
+        write_unitig(
+            &mut from_graph.0,
+            &mut from_graph.1,
+            &UnitigGraph::new(k, &solid).unitigs,
+            1,
+        )
+        .unwrap();
+        assert_eq!(from_stream, from_graph);
+    }
+
+    #[test]
+    fn jump() {
+        let seq = b"TGGGTAAAGGTGGCGCGGGG";
+        let k = 7;
+
+        let counter = crate::counter::Counter::new(k, 8);
+        counter.add_sequence(seq);
+        counter.add_sequence(seq);
+        counter.set(crate::kmer::seq2bit(&seq[5..12]), 1);
+
+        let mut solid = graph::kmer::Graph::new(counter.solidity(1), k, 2);
+        let unitigs = build_unitigs(k, &solid);
+
+        assert_eq!(unitigs.len(), 1);
+        assert_eq!(unitigs[0].seq.len(), seq.len());
+        assert_eq!(unitigs[0].jumps.len(), 1);
+        assert_eq!(unitigs[0].skipped(), 1);
+        assert_eq!(unitigs[0].inferred(), 1);
+
+        solid.set_abundance(counter);
+        let unitigs = build_unitigs(k, &solid);
+        assert_eq!(unitigs[0].inferred(), 0);
+    }
+
+    #[test]
+    fn transitive_reduction() {
+        let seq = b"TTGCAGTCCGAAAAAATGCCAGATCTTG";
+        let k = 7;
+
+        let counter = crate::counter::Counter::new(k, 8);
+        counter.add_sequence(seq);
+        counter.set(crate::kmer::seq2bit(b"GAAAAAA"), 0);
+
+        /* CGAAAAA jump to AAAAAAT and AAAAATG, AAAAATG is the next kmer of AAAAAAT */
+        let mut solid = graph::kmer::Graph::new(counter.solidity(0), k, 2);
+        let unitigs = build_unitigs(k, &solid);
+
+        assert_eq!(unitigs.len(), 2);
+        assert!(unitigs.iter().all(|unitig| unitig.seq.len() < seq.len()));
+
+        assert_eq!(solid.transitive_reduction(), 1);
+        let unitigs = build_unitigs(k, &solid);
+
+        assert_eq!(unitigs.len(), 1);
+        assert_eq!(unitigs[0].seq.len(), seq.len());
+    }
+
+    #[test]
+    fn transitive_reduction_without_chain() {
+        let seq = b"GCTAAAGACAATTACATAACATACACGTCA";
+        let other = b"ACGAAACTTGGACATAGCTTGGCCCAGT";
+        let k = 7;
+
+        let counter = crate::counter::Counter::new(k, 8);
+        counter.add_sequence(seq);
+        counter.add_sequence(other);
+        counter.set(crate::kmer::seq2bit(&seq[12..19]), 0);
+
+        /* other reach a jump end of seq by a direct step, but no chain from seq reach it */
+        let mut solid = graph::kmer::Graph::new(counter.solidity(0), k, 2);
+        let before: Vec<Vec<u8>> = build_unitigs(k, &solid)
+            .into_iter()
+            .map(|unitig| unitig.seq)
+            .collect();
+
+        assert_eq!(solid.transitive_reduction(), 0);
+        let after: Vec<Vec<u8>> = build_unitigs(k, &solid)
+            .into_iter()
+            .map(|unitig| unitig.seq)
+            .collect();
+
+        assert_eq!(before.len(), 3);
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn abundance() {
+        let seq = b"TGGGTAAAGGTGGCGCGGGG";
+        let k = 7;
+
+        let counter = crate::counter::Counter::new(k, 8);
+        counter.add_sequence(seq);
+        counter.add_sequence(seq);
+        counter.add_sequence(&seq[..10]);
+
+        let mut solid = graph::kmer::Graph::new(counter.solidity(0), k, 1);
+        solid.set_abundance(counter);
+        let unitig_graph = UnitigGraph::new(k, &solid);
+
+        assert_eq!(
+            unitig_graph.unitigs[0].abundance,
+            Some(Abundance {
+                sum: 32,
+                mean: 32.0 / 14.0,
+                median: 2,
+            })
+        );
+    }
 }
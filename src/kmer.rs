@@ -0,0 +1,134 @@
+/*
+Copyright (c) 2020 Pierre Marijon <pmarijon@mmci.uni-saarland.de>
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+
+/* Same 2 bit encoding as cocktail (A: 0, C: 1, T: 2, G: 3) but on 128 bits, kmer size up to 63 */
+
+pub const MAX_K: u8 = 63;
+
+pub fn nuc2bit(nuc: u8) -> u128 {
+    ((nuc >> 1) & 0b11) as u128
+}
+
+pub fn seq2bit(subseq: &[u8]) -> u128 {
+    let mut kmer: u128 = 0;
+
+    for n in subseq {
+        kmer <<= 2;
+        kmer |= nuc2bit(*n);
+    }
+
+    kmer
+}
+
+pub fn bit2nuc(bit: u128) -> u8 {
+    match bit & 0b11 {
+        0 => b'A',
+        1 => b'C',
+        2 => b'T',
+        _ => b'G',
+    }
+}
+
+pub fn kmer2seq(mut kmer: u128, k: u8) -> String {
+    let mut buffer = vec![0; k as usize];
+
+    for i in (0..k).rev() {
+        buffer[i as usize] = bit2nuc(kmer);
+        kmer >>= 2;
+    }
+
+    String::from_utf8(buffer).unwrap()
+}
+
+pub fn mask(k: u8) -> u128 {
+    (1 << (k as u32 * 2)) - 1
+}
+
+pub fn comp(kmer: u128, k: u8) -> u128 {
+    kmer ^ (0xAAAA_AAAA_AAAA_AAAA_AAAA_AAAA_AAAA_AAAA & mask(k))
+}
+
+/* reverse all bits, swap the two bits of each nucleotide, and shift back */
+pub fn rev(kmer: u128, k: u8) -> u128 {
+    let reverse = kmer.reverse_bits();
+    let swap = ((reverse >> 1) & 0x5555_5555_5555_5555_5555_5555_5555_5555)
+        | ((reverse & 0x5555_5555_5555_5555_5555_5555_5555_5555) << 1);
+
+    swap >> (128 - k as u32 * 2)
+}
+
+pub fn revcomp(kmer: u128, k: u8) -> u128 {
+    rev(comp(kmer, k), k)
+}
+
+pub fn parity_even(kmer: u128) -> bool {
+    kmer.count_ones() & 1 == 0
+}
+
+pub fn cannonical(kmer: u128, k: u8) -> u128 {
+    if parity_even(kmer) {
+        kmer
+    } else {
+        revcomp(kmer, k)
+    }
+}
+
+pub fn remove_first_bit(kmer: u128) -> u128 {
+    kmer >> 1
+}
+
+pub fn hash2kmer(hash: u128) -> u128 {
+    let kmer = hash << 1;
+
+    if parity_even(kmer) {
+        kmer
+    } else {
+        kmer | 1
+    }
+}
+
+pub fn get_kmer_space_size(k: u8) -> u128 {
+    1 << (k as u32 * 2)
+}
+
+pub fn get_hash_space_size(k: u8) -> u128 {
+    1 << (k as u32 * 2 - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revcomp_seq() {
+        let seq = b"TGGGTAAAGGTGGCGCGGGGACTGACGTTAGCCATTGACCAGTAGGCATTGACGGATTAGCAC";
+
+        for k in &[1, 7, 31, 32, 33, 63] {
+            let subseq = &seq[..*k as usize];
+
+            assert_eq!(
+                revcomp(seq2bit(subseq), *k),
+                seq2bit(&bio::alphabets::dna::revcomp(subseq))
+            );
+        }
+    }
+}
@@ -0,0 +1,57 @@
+/*
+Copyright (c) 2019 Pierre Marijon <pmarijon@mmci.uni-saarland.de>
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+
+/* crate declaration */
+/* cli management*/
+extern crate structopt;
+extern crate termcolor;
+
+/* error and logging */
+extern crate anyhow;
+#[macro_use]
+extern crate log;
+extern crate thiserror;
+
+extern crate bio;
+extern crate bv;
+extern crate cocktail;
+extern crate itertools;
+extern crate niffler;
+extern crate pcon;
+extern crate rayon;
+
+/* local mod */
+pub mod cli;
+pub mod counter;
+pub mod error;
+pub mod graph;
+pub mod kmer;
+pub mod utils;
+
+pub fn build_unitig_graph<S>(solidity: S, k: u8, edge_threshold: u8) -> graph::unitig::UnitigGraph
+where
+    S: Into<graph::kmer::Solidity>,
+{
+    let solid = graph::kmer::Graph::new(solidity.into(), k, edge_threshold);
+
+    graph::unitig::UnitigGraph::new(k, &solid)
+}
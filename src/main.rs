@@ -21,42 +21,25 @@ SOFTWARE.
  */
 
 /* crate declaration */
+extern crate cabanis;
+
 /* cli management*/
-#[macro_use]
 extern crate structopt;
-extern crate termcolor;
+
+extern crate rayon;
 
 /* error and logging */
-#[macro_use]
 extern crate anyhow;
 #[macro_use]
 extern crate log;
-extern crate thiserror;
-
-extern crate bio;
-extern crate bv;
-extern crate cocktail;
-extern crate itertools;
-extern crate niffler;
-extern crate pcon;
-extern crate petgraph;
-
-/* local mod */
-mod cli;
-mod error;
-mod graph;
-mod utils;
-
-/* std use */
-use std::io::Write;
 
 /* crate use */
 use anyhow::{Context, Result};
-use itertools::Itertools;
 use structopt::StructOpt;
 
 /* local use */
-use error::Error;
+use cabanis::error::Error;
+use cabanis::{cli, graph, utils};
 
 fn main() -> Result<()> {
     env_logger::init();
@@ -67,24 +50,102 @@ fn main() -> Result<()> {
         return cli::unicorn();
     }
 
-    let (k, data) = utils::get_count(&params)?;
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(params.threads)
+        .build_global()?;
 
-    let solid = graph::kmer::Graph::new(data, k, params.edge_threshold);
+    let (k, data, counter) = utils::get_count(&params)?;
+
+    let mut solid = graph::kmer::Graph::new(data, k, params.edge_threshold);
+    if let Some(counter) = counter {
+        solid.set_abundance(counter);
+    }
+
+    if params.transitive_reduction {
+        info!("Begin of transitive reduction");
+        let nb_edge = solid.transitive_reduction();
+        info!("{} jump edges removed", nb_edge);
+        info!("End of transitive reduction");
+    }
 
     if let Some(out_path) = &params.kmer {
         info!("Begin of kmer graph building");
         let mut kmer_writer =
-            std::io::BufWriter::new(std::fs::File::create(&out_path).with_context(|| {
+            std::io::BufWriter::new(std::fs::File::create(out_path).with_context(|| {
                 Error::CantWriteFile {
                     filename: out_path.to_string(),
                 }
             })?);
 
-        graph::kmer::write_kmer_graph(&mut kmer_writer, k, &solid)?;
+        graph::kmer::write_kmer_graph(&mut kmer_writer, k, &solid, params.gfa_version)?;
         info!("End of kmer graph building");
     }
 
+    if let Some(tip_length) = params.tip_length {
+        info!("Begin of tip clipping");
+        let max_len = if params.tip_unit == "kmer" {
+            tip_length + k as usize - 1
+        } else {
+            tip_length
+        };
+
+        let nb_tip = graph::simplify::clip_tips(&mut solid, max_len);
+        info!("{} tips removed", nb_tip);
+        info!("End of tip clipping");
+    }
+
+    if params.pop_bubbles {
+        info!("Begin of bubble popping");
+        let bubbles = graph::simplify::pop_bubbles(&mut solid, params.bubble_length_ratio);
+        info!("{} bubbles popped", bubbles.len());
+
+        if let Some(out_path) = &params.bubble_variants {
+            let mut variants_writer =
+                std::io::BufWriter::new(std::fs::File::create(out_path).with_context(|| {
+                    Error::CantWriteFile {
+                        filename: out_path.to_string(),
+                    }
+                })?);
+
+            graph::simplify::write_bubbles(&mut variants_writer, &bubbles)?;
+        }
+        info!("End of bubble popping");
+    }
+
+    if params.min_coverage.is_some() || params.relative_coverage.is_some() {
+        info!("Begin of low coverage unitig removal");
+        let nb_tig = graph::simplify::remove_low_coverage(
+            &mut solid,
+            params.min_coverage,
+            params.relative_coverage,
+        );
+        info!("{} low coverage unitigs removed", nb_tig);
+        info!("End of low coverage unitig removal");
+    }
+
     info!("Begin of unitig building");
+    let mut unitig_graph = graph::unitig::UnitigGraph::new(k, &solid);
+    info!("End of unitig building");
+
+    let mut paths = Vec::new();
+    if params.resolve_repeats.is_some() || params.gfa_paths || params.read_paths.is_some() {
+        info!("Begin of read threading");
+        paths = utils::read_paths(&params, &solid, &unitig_graph)?;
+        info!("{} distinct paths supported by reads", paths.len());
+        info!("End of read threading");
+    }
+
+    if let Some(min_support) = params.resolve_repeats {
+        info!("Begin of repeat resolution");
+        let (resolved, resolved_paths, nb_repeat) =
+            graph::path::resolve_repeats(&unitig_graph, &solid, &paths, min_support);
+        unitig_graph = resolved;
+        paths = resolved_paths;
+        info!("{} repeats resolved", nb_repeat);
+        info!("End of repeat resolution");
+    }
+
+    info!("Begin of unitig graph writting");
     let mut unitigs_writer =
         std::io::BufWriter::new(std::fs::File::create(&params.unitigs).with_context(|| {
             Error::CantWriteFile {
@@ -98,62 +159,63 @@ fn main() -> Result<()> {
                 filename: params.graph.clone(),
             }
         })?);
-    writeln!(graph_writer, "H\tVN:Z:1.0")?;
+    graph::gfa::write_header(&mut graph_writer, params.gfa_version)?;
 
-    let (ends2tig, mut unitig_graph) =
-        graph::unitig::write_unitig(&mut unitigs_writer, &mut graph_writer, k, &solid)?;
-    info!("End of unitig building");
-
-    info!("Begin of unitg graph building");
-    unitig_graph = graph::unitig::add_missing_edge(solid, k, unitig_graph);
-    info!("End of unitig graph building");
+    graph::unitig::write_unitig(
+        &mut unitigs_writer,
+        &mut graph_writer,
+        &unitig_graph.unitigs,
+        params.gfa_version,
+    )?;
 
-    info!("Begin of unitig graph writting");
+    info!("\tBegin of link record writing");
+    graph::unitig::write_links(&mut graph_writer, &unitig_graph, params.gfa_version)?;
+    info!("\tEnd of link record writing");
 
-    let mut paralelle_tig = std::collections::HashSet::new();
-    for tigs in ends2tig.values() {
-        if tigs.len() > 1 {
-            for tigs2 in tigs.iter().combinations(2) {
-                paralelle_tig.insert(utils::normalize_usize_2tuple((*tigs2[0], *tigs2[1])));
-            }
-        }
+    if params.gfa_paths {
+        graph::path::write_gfa(&mut graph_writer, &paths, params.gfa_version)?;
     }
 
-    info!("\tBegin of L record writing");
-    for node in unitig_graph.nodes() {
-        if let graph::unitig::Node::Tig(n) = node {
-            if n.circular {
-                writeln!(graph_writer, "L\t{}\t-\t{}\t+\t14M", n.id, n.id)?;
-            }
-        }
+    if let Some(out_path) = &params.read_paths {
+        let mut paths_writer =
+            std::io::BufWriter::new(std::fs::File::create(out_path).with_context(|| {
+                Error::CantWriteFile {
+                    filename: out_path.to_string(),
+                }
+            })?);
+
+        graph::path::write_tsv(&mut paths_writer, &paths)?;
     }
+    info!("End of unitig graph writting");
 
-    for link in graph::unitig::tig_kmer_tig(&unitig_graph) {
-        if paralelle_tig.contains(&utils::normalize_usize_2tuple((link.0, link.2))) {
-            continue;
-        }
+    if let Some(out_path) = &params.contigs {
+        info!("Begin of contig building");
+        let contigs = graph::contig::build_contigs(&unitig_graph, params.dominance_ratio);
+        info!("{} contigs build", contigs.len());
 
-        writeln!(
-            graph_writer,
-            "L\t{}\t{}\t{}\t{}\t14M",
-            link.0, link.1, link.2, link.3
-        )?;
+        let mut contigs_writer =
+            std::io::BufWriter::new(std::fs::File::create(out_path).with_context(|| {
+                Error::CantWriteFile {
+                    filename: out_path.to_string(),
+                }
+            })?);
+
+        graph::contig::write_contigs(&mut contigs_writer, &contigs)?;
+        info!("End of contig building");
     }
 
-    for link in graph::unitig::tig_kmer_kmer_tig(&unitig_graph) {
-        if paralelle_tig.contains(&utils::normalize_usize_2tuple((link.0, link.2))) {
-            continue;
-        }
+    if let Some(out_path) = &params.dot {
+        info!("Begin of dot writting");
+        let mut dot_writer =
+            std::io::BufWriter::new(std::fs::File::create(out_path).with_context(|| {
+                Error::CantWriteFile {
+                    filename: out_path.to_string(),
+                }
+            })?);
 
-        writeln!(
-            graph_writer,
-            "L\t{}\t{}\t{}\t{}\t14M",
-            link.0, link.1, link.2, link.3
-        )?;
+        graph::unitig::write_dot(&mut dot_writer, &unitig_graph.graph)?;
+        info!("End of dot writting");
     }
 
-    info!("\tEnd of L record writing");
-    info!("End of unitig graph writting");
-
     Ok(())
 }
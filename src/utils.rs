@@ -22,7 +22,12 @@ SOFTWARE.
 
 /* crate use */
 use anyhow::{Context, Result};
+use bv::BitsMut;
 use niffler;
+use rayon::prelude::*;
+
+/* standard use */
+use std::io::{BufRead, Write};
 
 /* local mod */
 use crate::cli;
@@ -30,56 +35,108 @@ use crate::error::Error;
 use crate::graph;
 
 pub fn build_tig(
-    kmer: u64,
+    kmer: u128,
     k: u8,
     solid: &graph::kmer::Graph,
-    visited: &mut graph::kmer::Viewed,
-) -> Option<(std::collections::VecDeque<u8>, u64, u64)> {
+    visited: &graph::kmer::Viewed,
+) -> Option<(
+    std::collections::VecDeque<u8>,
+    u128,
+    u128,
+    std::collections::VecDeque<u8>,
+)> {
     let mut tig = std::collections::VecDeque::new();
+    let mut gaps = std::collections::VecDeque::new();
 
     let mut current = kmer;
-    for n in cocktail::kmer::kmer2seq(current, k).bytes() {
+    for n in crate::kmer::kmer2seq(current, k).bytes() {
         tig.push_back(n);
     }
 
+    /* with jumps a cycle can be enter without come back to the seed */
+    let mut seen = rustc_hash::FxHashSet::default();
+    seen.insert(kmer);
+
     /* if a unitig with size equal to k and nb_pred < 2 || nb_succ < 2 it's not a valid unitig */
     let mut nb_pred = 0;
     let mut nb_succ = 0;
 
+    /* branching kmer at tig ends aren't mark as visited, they can be the seed of another tig */
+    let mut branching = false;
+    let mut cycle_min = kmer;
     while let Some((pred, ovl_len)) = solid.predecessors(current) {
         nb_pred = pred.len();
         if pred.len() != 1 {
+            branching = true;
             break;
         }
 
         if let Some((succ, _)) = solid.successors(current) {
             if succ.len() != 1 {
+                branching = true;
                 break;
             }
         }
 
+        visited.insert(current);
+
+        /* walk come back to the seed, circular tig always start by the smallest kmer of the cycle */
+        if pred[0] == kmer {
+            if cycle_min != kmer {
+                return build_tig(cycle_min, k, solid, visited);
+            }
+
+            add_kmer_in_tig(kmer, k, ovl_len, &mut tig, true);
+            gaps.push_front(ovl_len);
+
+            let cano = crate::kmer::cannonical(kmer, k);
+            return Some((tig, cano, cano, gaps));
+        }
+
+        if !seen.insert(pred[0]) {
+            break;
+        }
+
         add_kmer_in_tig(pred[0], k, ovl_len, &mut tig, true);
+        gaps.push_front(ovl_len);
         current = pred[0];
+
+        if crate::kmer::cannonical(current, k) < crate::kmer::cannonical(cycle_min, k) {
+            cycle_min = current;
+        }
+    }
+    if !branching {
         visited.insert(current);
     }
     let begin = current;
 
     current = kmer;
 
+    branching = false;
     while let Some((succ, ovl_len)) = solid.successors(current) {
         nb_succ = succ.len();
         if succ.len() != 1 {
+            branching = true;
             break;
         }
 
         if let Some((pred, _)) = solid.predecessors(current) {
             if pred.len() != 1 {
+                branching = true;
                 break;
             }
         }
 
+        visited.insert(current);
+        if !seen.insert(succ[0]) {
+            break;
+        }
+
         add_kmer_in_tig(succ[0], k, ovl_len, &mut tig, false);
+        gaps.push_back(ovl_len);
         current = succ[0];
+    }
+    if !branching {
         visited.insert(current);
     }
 
@@ -89,32 +146,33 @@ pub fn build_tig(
 
     Some((
         tig,
-        cocktail::kmer::cannonical(begin, k),
-        cocktail::kmer::cannonical(current, k),
+        crate::kmer::cannonical(begin, k),
+        crate::kmer::cannonical(current, k),
+        gaps,
     ))
 }
 
 fn add_kmer_in_tig(
-    kmer: u64,
+    kmer: u128,
     k: u8,
     not_ovl_len: u8,
     tig: &mut std::collections::VecDeque<u8>,
     in_front: bool,
 ) {
     if in_front {
-        let seq = cocktail::kmer::kmer2seq(kmer, k);
+        let seq = crate::kmer::kmer2seq(kmer, k);
         for n in seq[..not_ovl_len as usize].bytes().rev() {
             tig.push_front(n);
         }
     } else {
-        let seq = cocktail::kmer::kmer2seq(kmer, k);
+        let seq = crate::kmer::kmer2seq(kmer, k);
         for n in seq[(k - not_ovl_len) as usize..].bytes() {
             tig.push_back(n);
         }
     }
 }
 
-pub fn normalize_u64_2tuple(mut a: (u64, u64)) -> (u64, u64) {
+pub fn normalize_u128_2tuple(mut a: (u128, u128)) -> (u128, u128) {
     if a.0 > a.1 {
         std::mem::swap(&mut a.0, &mut a.1);
     }
@@ -130,53 +188,476 @@ pub fn normalize_usize_2tuple(mut a: (usize, usize)) -> (usize, usize) {
     a
 }
 
-pub fn get_count(params: &cli::Command) -> Result<(u8, bv::BitVec<u8>)> {
-    match &params.subcmd {
-        cli::SubCommand::Count(subcmd_params) => {
-            info!("Begin of read solidity information");
+fn open_reads(input: &str) -> Result<Box<dyn std::io::BufRead>> {
+    let raw: Box<dyn std::io::Read> = if input == "-" {
+        Box::new(std::io::stdin())
+    } else {
+        Box::new(std::io::BufReader::new(
+            std::fs::File::open(input).with_context(|| Error::CantReadFile {
+                filename: input.to_string(),
+            })?,
+        ))
+    };
+
+    /* niffler can't sniff the format of an empty input */
+    let mut raw = std::io::BufReader::new(raw);
+    if raw
+        .fill_buf()
+        .with_context(|| Error::ReadingError {
+            filename: input.to_string(),
+        })?
+        .is_empty()
+    {
+        return Ok(Box::new(raw));
+    }
+
+    let (reader, _) = niffler::get_reader(Box::new(raw))?;
+
+    Ok(Box::new(std::io::BufReader::new(reader)))
+}
+
+pub fn process_file<H>(input: &str, record_buffer: usize, handle: &mut H) -> Result<()>
+where
+    H: FnMut(&[&[u8]]),
+{
+    let mut reader = open_reads(input)?;
+
+    let first = reader
+        .fill_buf()
+        .with_context(|| Error::ReadingError {
+            filename: input.to_string(),
+        })?
+        .first()
+        .copied();
+
+    match first {
+        Some(b'>') => {
+            info!("Read fasta file {}", input);
+            process_records(
+                bio::io::fasta::Reader::new(reader).records(),
+                record_buffer,
+                input,
+                |record| record.seq(),
+                handle,
+            )
+        }
+        Some(b'@') => {
+            info!("Read fastq file {}", input);
+            process_records(
+                bio::io::fastq::Reader::new(reader).records(),
+                record_buffer,
+                input,
+                |record| record.seq(),
+                handle,
+            )
+        }
+        None => {
+            warn!("File {} is empty", input);
+            Ok(())
+        }
+        Some(_) => Err(Error::UnknownFormat {
+            filename: input.to_string(),
+        }
+        .into()),
+    }
+}
+
+fn process_records<I, T, E, F, H>(
+    mut records: I,
+    record_buffer: usize,
+    input: &str,
+    seq: F,
+    handle: &mut H,
+) -> Result<()>
+where
+    I: Iterator<Item = std::result::Result<T, E>>,
+    E: std::error::Error + Send + Sync + 'static,
+    F: Fn(&T) -> &[u8],
+    H: FnMut(&[&[u8]]),
+{
+    let mut batch = Vec::with_capacity(record_buffer);
+    loop {
+        batch.clear();
+        for record in records.by_ref().take(record_buffer) {
+            batch.push(record.with_context(|| Error::ReadingError {
+                filename: input.to_string(),
+            })?);
+        }
+
+        if batch.is_empty() {
+            break;
+        }
+
+        let seqs: Vec<&[u8]> = batch.iter().map(&seq).collect();
+        handle(&seqs);
+    }
+
+    Ok(())
+}
+
+fn abundance_threshold(histogram: &[u64]) -> u8 {
+    info!("Begin of abundance threshold selection");
 
-            let (k, data) = cocktail::io::read_solidity_bitfield(
-                std::io::BufReader::new(std::fs::File::open(&subcmd_params.input).with_context(
-                    || Error::CantReadFile {
-                        filename: subcmd_params.input.clone(),
-                    },
-                )?),
-                std::fs::metadata(&subcmd_params.input).unwrap().len(),
-            );
+    for (abundance, nb_kmer) in histogram.iter().enumerate().skip(1) {
+        if *nb_kmer != 0 {
+            debug!("abundance {} nb kmer {}", abundance, nb_kmer);
+        }
+    }
 
-            info!("End of read solidity information");
+    let threshold = match crate::counter::first_valley(histogram) {
+        Some(valley) => valley,
+        None => {
+            warn!("No valley found in kmer spectrum, abundance threshold set to 1");
+            1
+        }
+    };
 
-            Ok((k, data))
+    info!("Abundance threshold set to {}", threshold);
+    info!("End of abundance threshold selection");
+
+    threshold
+}
+
+fn write_histogram(path: &str, histogram: &[u64]) -> Result<()> {
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path).with_context(|| {
+        Error::CantWriteFile {
+            filename: path.to_string(),
+        }
+    })?);
+
+    for (abundance, nb_kmer) in histogram.iter().enumerate().skip(1) {
+        writeln!(writer, "{}\t{}", abundance, nb_kmer).with_context(|| Error::WritingError {
+            filename: path.to_string(),
+        })?;
+    }
+
+    Ok(())
+}
+
+/* solidity bitfield store 4^k / 2 bits, 1 GiB with k = 17 */
+const SOLIDITY_MAX_K: u8 = 17;
+
+fn write_solidity(path: &str, k: u8, solidity: &graph::kmer::Solidity) -> Result<()> {
+    let writer = std::io::BufWriter::new(std::fs::File::create(path).with_context(|| {
+        Error::CantWriteFile {
+            filename: path.to_string(),
+        }
+    })?);
+
+    match solidity {
+        graph::kmer::Solidity::Bitfield(bitfield) => {
+            cocktail::io::write_solidity_bitfield(writer, k, bitfield)
+        }
+        graph::kmer::Solidity::Kmers(kmers) => {
+            let mut bitfield = bv::BitVec::new_fill(false, cocktail::kmer::get_hash_space_size(k));
+
+            for kmer in kmers {
+                bitfield.set_bit(crate::kmer::remove_first_bit(*kmer) as u64, true);
+            }
+
+            cocktail::io::write_solidity_bitfield(writer, k, &bitfield)
+        }
+    }
+
+    Ok(())
+}
+
+pub fn get_count(
+    params: &cli::Command,
+) -> Result<(u8, graph::kmer::Solidity, Option<crate::counter::Counts>)> {
+    match &params.subcmd {
+        cli::SubCommand::Count(subcmd_params) => {
+            if let Some(count_path) = &subcmd_params.count {
+                info!("Begin of read count information");
+
+                let count = pcon::count::Count::deserialize(std::io::BufReader::new(
+                    std::fs::File::open(count_path).with_context(|| Error::CantReadFile {
+                        filename: count_path.clone(),
+                    })?,
+                ));
+
+                let counter = crate::counter::Counts::Pcon(count);
+
+                info!("End of read count information");
+
+                let abundance_min = match subcmd_params.abundance_min {
+                    cli::AbundanceMin::Value(value) => value,
+                    cli::AbundanceMin::Auto => abundance_threshold(&counter.histogram()),
+                };
+
+                Ok((counter.k(), counter.solidity(abundance_min), Some(counter)))
+            } else if let Some(input) = &subcmd_params.input {
+                info!("Begin of read solidity information");
+
+                let (k, data) = cocktail::io::read_solidity_bitfield(
+                    std::io::BufReader::new(std::fs::File::open(input).with_context(|| {
+                        Error::CantReadFile {
+                            filename: input.clone(),
+                        }
+                    })?),
+                    std::fs::metadata(input).unwrap().len(),
+                );
+
+                info!("End of read solidity information");
+
+                Ok((k, data.into(), None))
+            } else {
+                Err(Error::NotReachableCode {
+                    name: "count subcommand without input".to_string(),
+                }
+                .into())
+            }
         }
         cli::SubCommand::Reads(subcmd_params) => {
+            if subcmd_params.kmer_size < 1 {
+                return Err(Error::KmerSizeTooSmall {
+                    k: subcmd_params.kmer_size,
+                    min: 1,
+                }
+                .into());
+            }
+
+            /* with an even k a kmer and its reverse complement have the same parity */
+            let k = if subcmd_params.kmer_size % 2 == 0 {
+                warn!(
+                    "Kmer size {} is even, {} is used",
+                    subcmd_params.kmer_size,
+                    subcmd_params.kmer_size - 1
+                );
+                subcmd_params.kmer_size - 1
+            } else {
+                subcmd_params.kmer_size
+            };
+
+            if k > crate::kmer::MAX_K {
+                return Err(Error::KmerSizeTooLarge {
+                    k,
+                    max: crate::kmer::MAX_K,
+                }
+                .into());
+            }
+
+            if subcmd_params.solidity.is_some() && k > SOLIDITY_MAX_K {
+                return Err(Error::KmerSizeTooLarge {
+                    k,
+                    max: SOLIDITY_MAX_K,
+                }
+                .into());
+            }
+
             info!("Begin of kmer counting");
 
-            let mut count = pcon::count::Count::new(subcmd_params.kmer_size, 8);
+            let counter = crate::counter::Counter::new(k, subcmd_params.counter_bits);
+
+            for input in subcmd_params.input.iter() {
+                process_file(input, subcmd_params.record_buffer, &mut |seqs| {
+                    seqs.par_iter().for_each(|seq| counter.add_sequence(seq))
+                })?;
+            }
+
+            info!("End of kmer counting");
 
-            let (reader, _) = niffler::get_reader(Box::new(std::io::BufReader::new(
-                std::fs::File::open(&subcmd_params.input).with_context(|| Error::CantReadFile {
-                    filename: subcmd_params.input.clone(),
-                })?,
-            )))?;
+            let histogram = match (subcmd_params.abundance_min, &subcmd_params.histogram) {
+                (cli::AbundanceMin::Value(_), None) => Vec::new(),
+                _ => counter.histogram(),
+            };
+
+            if let Some(path) = &subcmd_params.histogram {
+                info!("Begin of histogram writing");
+                write_histogram(path, &histogram)?;
+                info!("End of histogram writing");
+            }
 
-            let fasta_reader = bio::io::fasta::Reader::new(reader);
+            let abundance_min = match subcmd_params.abundance_min {
+                cli::AbundanceMin::Value(value) => value,
+                cli::AbundanceMin::Auto => abundance_threshold(&histogram),
+            };
 
-            for record in fasta_reader.records() {
-                let result = record.with_context(|| Error::ReadingError {
-                    filename: subcmd_params.input.clone(),
+            let solidity = counter.solidity(abundance_min);
+
+            if let Some(path) = &subcmd_params.solidity {
+                info!("Begin of solidity writing");
+                write_solidity(path, k, &solidity)?;
+                info!("End of solidity writing");
+            }
+
+            Ok((k, solidity, Some(counter.into())))
+        }
+    }
+}
+
+pub fn read_paths(
+    params: &cli::Command,
+    solid: &graph::kmer::Graph,
+    unitig_graph: &graph::unitig::UnitigGraph,
+) -> Result<Vec<(graph::path::Path, u64)>> {
+    let mut counts = rustc_hash::FxHashMap::default();
+
+    match &params.subcmd {
+        cli::SubCommand::Reads(subcmd_params) => {
+            let index = graph::path::KmerIndex::new(unitig_graph, solid);
+
+            for input in subcmd_params.input.iter() {
+                if input == "-" {
+                    warn!("Reads from stdin can't be read twice, they aren't threaded");
+                    continue;
+                }
+
+                process_file(input, subcmd_params.record_buffer, &mut |seqs| {
+                    graph::path::add_paths(&mut counts, seqs, &index, solid, unitig_graph)
                 })?;
+            }
+        }
+        cli::SubCommand::Count(_) => {
+            warn!("Count subcommand doesn't keep reads, they can't be threaded");
+        }
+    }
+
+    Ok(graph::path::sorted_paths(counts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use structopt::StructOpt;
 
-                count.add_sequence(result.seq());
+    fn temp_file(name: &str, content: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("cabanis_{}_{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+
+        path.to_str().unwrap().to_string()
+    }
+
+    fn reads_params(args: &[&str]) -> cli::Command {
+        let mut argv = vec!["cabanis", "-g", "graph.gfa", "-u", "unitigs.fasta", "reads"];
+        argv.extend_from_slice(args);
+
+        cli::Command::from_iter(&argv)
+    }
+
+    fn read_seqs(input: &str) -> Result<Vec<Vec<u8>>> {
+        let mut seqs = Vec::new();
+
+        process_file(input, 1, &mut |batch| {
+            seqs.extend(batch.iter().map(|seq| seq.to_vec()))
+        })?;
+
+        Ok(seqs)
+    }
+
+    const FASTA: &[u8] = b">0\nTGGGTAAAGGTGGCGCGGGG\n>1\nACTGACGTTAGCCATTG\n";
+
+    const FASTA_GZ: &[u8] =
+        b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x0d\xc4\xc1\x09\x00\x30\x0c\xc3\
+\xc0\xbf\xa7\x69\x17\x08\x18\x3f\xb4\x80\xf7\x9f\x25\xd1\x81\xe6\xa9\x40\x6d\xdf\x21\xe7\xd2\x7c\
+\x39\xc5\xa1\x35\x89\x5b\xb4\x4e\xb9\xec\xa7\x2d\x00\x00\x00";
+
+    #[test]
+    fn input_format() {
+        let expected = vec![
+            b"TGGGTAAAGGTGGCGCGGGG".to_vec(),
+            b"ACTGACGTTAGCCATTG".to_vec(),
+        ];
+
+        let fasta = temp_file("reads.fasta", FASTA);
+        assert_eq!(read_seqs(&fasta).unwrap(), expected);
+
+        let fastq = temp_file(
+            "reads.fastq",
+            b"@0\nTGGGTAAAGGTGGCGCGGGG\n+\nIIIIIIIIIIIIIIIIIIII\n@1\nACTGACGTTAGCCATTG\n+\nIIIIIIIIIIIIIIIII\n",
+        );
+        assert_eq!(read_seqs(&fastq).unwrap(), expected);
+
+        let gzip = temp_file("reads.fasta.gz", FASTA_GZ);
+        assert_eq!(read_seqs(&gzip).unwrap(), expected);
+
+        let empty = temp_file("empty.fasta", b"");
+        assert!(read_seqs(&empty).unwrap().is_empty());
+
+        let unknown = temp_file("reads.txt", b"TGGGTAAAGGTGGCGCGGGG\n");
+        let error = read_seqs(&unknown).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::UnknownFormat { .. })
+        ));
+
+        for path in &[fasta, fastq, gzip, empty, unknown] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn multiple_inputs() {
+        let fasta = temp_file("multi.fasta", FASTA);
+        let gzip = temp_file("multi.fasta.gz", FASTA_GZ);
+
+        let (k, _, counter) =
+            get_count(&reads_params(&["-i", &fasta, "-i", &gzip, "-k", "7"])).unwrap();
+        let counter = counter.unwrap();
+
+        for seq in &[&b"TGGGTAAAGGTGGCGCGGGG"[..], &b"ACTGACGTTAGCCATTG"[..]] {
+            for kmer in seq.windows(k as usize).map(crate::kmer::seq2bit) {
+                assert_eq!(counter.get(kmer), 2);
             }
+        }
 
-            count.clean_buckets();
+        std::fs::remove_file(fasta).unwrap();
+        std::fs::remove_file(gzip).unwrap();
+    }
 
-            info!("End of kmer counting");
+    #[test]
+    fn even_kmer_size() {
+        let seq = b"TGGGTAAAGGTGGCGCGGGGACTGACGTT";
+        let mut content = b">0\n".to_vec();
+        content.extend_from_slice(seq);
+        content.extend_from_slice(b"\n>1\n");
+        content.extend_from_slice(&bio::alphabets::dna::revcomp(&seq[..]));
+        content.push(b'\n');
+        let input = temp_file("even.fasta", &content);
+
+        let (k, _, counter) = get_count(&reads_params(&["-i", &input, "-k", "8"])).unwrap();
+        let counter = counter.unwrap();
+
+        assert_eq!(k, 7);
+        assert_eq!(counter.k(), 7);
+        for kmer in seq.windows(k as usize).map(crate::kmer::seq2bit) {
+            assert_eq!(counter.get(kmer), 2);
+            assert_eq!(counter.get(crate::kmer::revcomp(kmer, k)), 2);
+        }
 
-            Ok((
-                subcmd_params.kmer_size,
-                count.generate_bitfield(subcmd_params.abundance_min),
-            ))
+        std::fs::remove_file(input).unwrap();
+    }
+
+    #[test]
+    fn solidity_kmer_size() {
+        let input = temp_file("solidity.fasta", FASTA);
+        let output = temp_file("solidity.bin", b"");
+
+        match get_count(&reads_params(&["-i", &input, "-k", "21", "-s", &output])) {
+            Err(error) => match error.downcast_ref::<Error>() {
+                Some(Error::KmerSizeTooLarge { k: 21, max }) => assert_eq!(*max, SOLIDITY_MAX_K),
+                other => panic!("unexpected error {:?}", other),
+            },
+            Ok(_) => panic!("solidity with kmer size 21 is accepted"),
+        }
+
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn null_kmer_size() {
+        let input = temp_file("null.fasta", FASTA);
+
+        match get_count(&reads_params(&["-i", &input, "-k", "0"])) {
+            Err(error) => match error.downcast_ref::<Error>() {
+                Some(Error::KmerSizeTooSmall { k: 0, min: 1 }) => (),
+                other => panic!("unexpected error {:?}", other),
+            },
+            Ok(_) => panic!("kmer size 0 is accepted"),
         }
+
+        std::fs::remove_file(input).unwrap();
     }
 }